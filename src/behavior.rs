@@ -4,19 +4,69 @@ use core::{
     task::{Context, Poll},
 };
 
-use crate::packet::{GolayDecoderResult, PacketWithGolay, PacketWithInterleave, PacketWithoutDC};
+use crate::frame::Framing;
+use crate::line::LineCode as _;
+use crate::packet::GolayDecoderResult;
+use crate::stream::StreamDecoder;
 
+// Decode a packet yielding between the Golay codewords, the Golay
+// decoder is by far the slowest part of the decoding
 pub async fn decode_with_breaks(packet: &[u8]) -> GolayDecoderResult {
-    let p = PacketWithoutDC::new(packet);
-    let p2 = PacketWithInterleave::from(&p);
+    decode_with_budget(packet, 1).await
+}
 
-    yield_now().await;
+// Decode a 6b/8b packet yielding after every `codewords` Golay codewords
+pub async fn decode_with_budget(packet: &[u8], codewords: usize) -> GolayDecoderResult {
+    decode_framed_with_budget(&Framing::NONE, packet, codewords).await
+}
 
-    let p3 = PacketWithGolay::from(&p2);
+// Same as decode_with_budget, for packets sent with the line code and
+// packet layout of the framing
+pub async fn decode_framed_with_budget(
+    framing: &Framing<'_>,
+    packet: &[u8],
+    codewords: usize,
+) -> GolayDecoderResult {
+    // Short packets are padded with zeros, the stream is always complete
+    let mut stream = StreamDecoder::for_framing(framing);
+    stream.push_slice(packet);
+    for _ in packet.len()..framing.size() {
+        stream.push(0);
+    }
 
     yield_now().await;
 
-    GolayDecoderResult::from(&p3)
+    decode_stream(&mut stream, codewords)
+        .await
+        .unwrap_or_default()
+}
+
+// Finish decoding of a packet received into the stream decoder, yielding
+// after every `codewords` Golay codewords. Returns None when the packet
+// was not fully received yet.
+//
+// The progress is kept in the stream decoder. Dropping the future cancels
+// the decoding after the last finished codeword, calling this again with
+// the same stream decoder resumes it.
+pub async fn decode_stream(
+    stream: &mut StreamDecoder,
+    codewords: usize,
+) -> Option<GolayDecoderResult> {
+    if !stream.received() {
+        return None;
+    }
+
+    loop {
+        for _ in 0..codewords.max(1) {
+            stream.step();
+        }
+
+        if stream.complete() {
+            return stream.result();
+        }
+
+        yield_now().await;
+    }
 }
 
 struct Yield(bool);
@@ -44,3 +94,80 @@ impl Future for Yield {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interleave::PacketInterleave;
+    use crate::line::LineCoding;
+    use crate::packet::{test_v2_packet, PacketWithoutDC};
+    use core::{pin::pin, task::Waker};
+
+    fn test_packet() -> PacketWithoutDC {
+        test_v2_packet(&[0x55; 11]).encode_for_transmit()
+    }
+
+    // Poll the future to completion, returns the result and the number of yields
+    fn run<F: Future>(f: F) -> (F::Output, usize) {
+        let mut f = pin!(f);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut yields = 0;
+        loop {
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(r) => return (r, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn test_budget() {
+        let packet = test_packet();
+        let expected = packet.decode();
+
+        for (budget, yields) in [(0, 8), (1, 8), (3, 3), (8, 1), (100, 1)] {
+            let (res, n) = run(decode_with_budget(&packet.data(), budget));
+            assert_eq!(res.data, expected.data);
+            assert_eq!(n, yields, "Budget {budget}");
+        }
+
+        // Short packets decode as if padded with zeros
+        let short = &packet.data()[..20];
+        let (res, _) = run(decode_with_budget(short, 8));
+        assert_eq!(res.data, PacketWithoutDC::new(short).decode().data);
+    }
+
+    #[test]
+    fn test_framed_budget() {
+        let packet = test_v2_packet(&[0x55; 11]);
+        let framing = Framing::NONE
+            .with_line_code(LineCoding::Pn9)
+            .with_packet_interleave(PacketInterleave::Sequential);
+        let radio = framing.encode_packet(&packet);
+
+        let (res, n) = run(decode_framed_with_budget(&framing, &radio, 4));
+        assert_eq!(res.data.data, packet.data);
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn test_cancel_resume() {
+        let packet = test_packet();
+        let mut stream = StreamDecoder::from(&packet);
+
+        {
+            let mut f = pin!(decode_stream(&mut stream, 2));
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(f.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(stream.decoded(), 2);
+
+        let (res, yields) = run(decode_stream(&mut stream, 2));
+        assert_eq!(res.unwrap().data, packet.decode().data);
+        assert_eq!(yields, 2);
+
+        let mut partial = StreamDecoder::new();
+        partial.push_slice(&packet.data()[..10]);
+        assert!(run(decode_stream(&mut partial, 1)).0.is_none());
+    }
+}
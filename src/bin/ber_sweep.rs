@@ -0,0 +1,243 @@
+// Packet and message error rates of the full encode/decode chain over
+// a simulated channel, printed as CSV.
+//
+//   cargo run --release --features sim --bin ber_sweep -- [--messages N] [--len BYTES] [--seed S]
+//
+// Every row is one message version, decoder variant, bit error rate and
+// mean burst length. A burst length of 1 means independent bit errors,
+// longer bursts use the Gilbert-Elliott model with the same average
+// bit error rate.
+
+use std::{
+    env,
+    future::Future,
+    pin::pin,
+    process,
+    task::{Context, Poll, Waker},
+};
+
+use laso_packet::{
+    behavior::decode_with_breaks,
+    message::{Message, MessageVersion},
+    packet::PacketData,
+    plan,
+    rx::RxMessageDecoder,
+    sim::{Channel, ChannelConfig, GilbertElliott},
+    tx::MessageSender,
+};
+
+const MAX_MESSAGE: usize = 64;
+
+const BER: [f64; 8] = [0.0, 1e-4, 3e-4, 1e-3, 3e-3, 1e-2, 3e-2, 1e-1];
+const BURSTS: [usize; 5] = [1, 4, 8, 16, 32];
+
+// Alternative decodings tried by the repairing decoder
+const REPAIR_ATTEMPTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Decoder {
+    Plain,
+    Repair,
+}
+
+struct Args {
+    messages: usize,
+    len: usize,
+    seed: u64,
+}
+
+#[derive(Default)]
+struct Counts {
+    packets: usize,
+    packet_errors: usize,
+    messages: usize,
+    message_errors: usize,
+    bits: usize,
+    bit_errors: usize,
+}
+
+fn versions() -> Vec<MessageVersion> {
+    vec![
+        #[cfg(feature = "legacy")]
+        MessageVersion::LegacyLaso,
+        MessageVersion::V2,
+        MessageVersion::V2Short,
+        MessageVersion::Naked,
+        MessageVersion::NakedShort,
+    ]
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: ber_sweep [--messages N] [--len BYTES] [--seed S]");
+    process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        messages: 1000,
+        len: 20,
+        seed: 1,
+    };
+
+    let mut it = env::args().skip(1);
+    while let Some(arg) = it.next() {
+        let value = it.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--messages" => args.messages = value.parse().unwrap_or_else(|_| usage()),
+            "--len" => args.len = value.parse().unwrap_or_else(|_| usage()),
+            "--seed" => args.seed = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    if args.messages == 0 {
+        eprintln!("At least one message is needed");
+        process::exit(2);
+    }
+    if args.len == 0 {
+        eprintln!("Messages need at least one byte");
+        process::exit(2);
+    }
+    if args.len > MAX_MESSAGE {
+        eprintln!("Messages are limited to {MAX_MESSAGE} bytes");
+        process::exit(2);
+    }
+    args
+}
+
+// The decoder never waits for anything, polling until ready is enough
+fn block_on<F: Future>(f: F) -> F::Output {
+    let mut f = pin!(f);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(r) = f.as_mut().poll(&mut cx) {
+            return r;
+        }
+    }
+}
+
+fn test_message(version: MessageVersion, len: usize, n: usize) -> Message<MAX_MESSAGE> {
+    let mut msg = Message {
+        version,
+        source_address: 0x1234,
+        packet_type: Some(0x10),
+        ..Default::default()
+    };
+
+    // Short messages are a single packet
+    let len = match version {
+        MessageVersion::V2Short | MessageVersion::NakedShort => {
+            let header = plan::header_size(version, msg.source_address, msg.packet_type);
+            len.min(plan::first_packet_capacity(version, header))
+        }
+        _ => len,
+    };
+    msg.data.extend((0..len).map(|b| (b * 31 + n) as u8));
+    msg
+}
+
+// Naked messages carry no packet type
+fn same_message(rx: &Message<MAX_MESSAGE>, msg: &Message<MAX_MESSAGE>) -> bool {
+    let naked = matches!(
+        msg.version,
+        MessageVersion::Naked | MessageVersion::NakedShort
+    );
+    rx.source_address == msg.source_address
+        && (naked || rx.packet_type == msg.packet_type)
+        && rx.data.starts_with(&msg.data)
+}
+
+fn run(
+    version: MessageVersion,
+    decoder: Decoder,
+    channel: &mut Channel,
+    args: &Args,
+    counts: &mut Counts,
+) {
+    for n in 0..args.messages {
+        let msg = test_message(version, args.len, n);
+        let mut sender = MessageSender::new(msg.clone());
+        let mut rx: RxMessageDecoder<MAX_MESSAGE> = RxMessageDecoder::default();
+        let mut failed = false;
+
+        while sender.data_to_send() {
+            let packet: PacketData = sender.packet();
+            counts.packets += 1;
+
+            let Some(received) = channel.transmit(&packet.encode_for_transmit()) else {
+                counts.packet_errors += 1;
+                failed = true;
+                continue;
+            };
+
+            let dec = block_on(decode_with_breaks(&received));
+            if dec.data.data != packet.data || dec.data.status.encode() != packet.status.encode() {
+                counts.packet_errors += 1;
+            }
+
+            if !failed {
+                let res = match decoder {
+                    Decoder::Plain => rx.append(&dec),
+                    Decoder::Repair => rx.append_with_repair(&dec, REPAIR_ATTEMPTS),
+                };
+                failed = res.is_err();
+            }
+        }
+
+        counts.messages += 1;
+        let ok = !failed
+            && rx
+                .take_message()
+                .is_some_and(|rx| same_message(&rx.msg, &msg));
+        if !ok {
+            counts.message_errors += 1;
+        }
+    }
+}
+
+fn main() {
+    let args = parse_args();
+
+    println!("version,decoder,ber,burst,packets,packet_errors,per,messages,message_errors,mer,measured_ber");
+    for version in versions() {
+        for decoder in [Decoder::Plain, Decoder::Repair] {
+            for ber in BER {
+                for burst in BURSTS {
+                    // Without errors the burst length does not matter
+                    if ber == 0.0 && burst > 1 {
+                        continue;
+                    }
+
+                    let config = if burst == 1 {
+                        ChannelConfig::ber(ber)
+                    } else {
+                        ChannelConfig {
+                            bursts: Some(GilbertElliott::with_ber(ber, burst as f64)),
+                            ..Default::default()
+                        }
+                    };
+                    let mut channel = Channel::new(config, args.seed);
+                    let mut counts = Counts::default();
+                    run(version, decoder, &mut channel, &args, &mut counts);
+                    counts.bits = (channel.stats.packets - channel.stats.dropped) * 256;
+                    counts.bit_errors = channel.stats.bit_errors;
+
+                    println!(
+                        "{:?},{:?},{},{},{},{},{:.6},{},{},{:.6},{:.6}",
+                        version,
+                        decoder,
+                        ber,
+                        burst,
+                        counts.packets,
+                        counts.packet_errors,
+                        counts.packet_errors as f64 / counts.packets as f64,
+                        counts.messages,
+                        counts.message_errors,
+                        counts.message_errors as f64 / counts.messages as f64,
+                        counts.bit_errors as f64 / counts.bits.max(1) as f64,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,633 @@
+// Command line tool for looking at LASO frames
+//
+//   laso encode [--version V] [--source N] [--type N] [--listen]
+//               [--preamble N] [--sync HEX] [--line-code C] [--packet-interleave L]
+//               PAYLOAD_HEX
+//   laso decode [--skip N] [--binary] [--line-code C] [--packet-interleave L] [FILE]
+//   laso explain [--line-code C] [--packet-interleave L] FRAME_HEX
+//   laso json [--skip N] [--binary] [--line-code C] [--packet-interleave L] [FILE]
+//   laso pcap [--skip N] [--binary] [--line-code C] [--packet-interleave L] [FILE]
+//             > capture.pcap
+//   laso dissector > laso.lua
+//   laso modulate [MODEM] IQ_FILE [FILE]
+//   laso demodulate [MODEM] [--sync HEX] [--sync-errors N] [--line-code C] IQ_FILE
+//
+// encode prints one frame per line as hex. decode reads hex frames (one
+// per line), or raw binary with --binary, from the file or stdin and
+// prints every stage of the receive pipeline, --skip drops the preamble
+// and sync word in front of each frame. explain annotates every bit of
+// a single frame. The line
+// code (6b8b, manchester, pn9 or none) is 6b8b unless given, it sets the
+// frame size. The packet interleave L (spread or sequential) is the layout
+// of the codewords in a packet, spread unless given.
+// json takes the same input as decode and prints an rtl_433 style JSON
+// line for every received message. decode, json and pcap read capture
+// files as well. pcap writes a Wireshark capture, dissector prints the
+// Lua dissector for it.
+//
+// modulate turns hex frames, including preamble and sync word (see encode),
+// into an IQ sample file. demodulate finds the packets after the sync word
+// in an IQ file and prints them as hex for decode. The MODEM options are
+// --format cu8|cs16, --rate SAMPLES_PER_S, --bitrate BITS_PER_S and
+// --deviation HZ for FSK or --ook.
+
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    process,
+};
+
+use laso_packet::{
+    capture::{CaptureReader, CaptureRecord, IoSink, CAPTURE_MAGIC},
+    frame::FrameSync,
+    frame::{Framing, MAX_FRAME_SIZE},
+    interleave::PacketInterleave,
+    json::{failed_to_json, to_json, RawPayload},
+    line::{LineCode as _, LineCoded, LineCoding},
+    message::{Message, MessageVersion},
+    modem::{read_iq, write_iq, Demodulator, ModemConfig, Modulation, Modulator, SampleFormat},
+    packet::{GolayDecoderResult, PacketWithGolay},
+    pcap::{lua_dissector, PcapWriter},
+    rx::{RxErrorKind, RxMessageDecoder, RxState},
+    stream::{StreamDecoder, CODEWORDS},
+    tx::MessageSender,
+};
+
+const MAX_MESSAGE: usize = 256;
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage:
+  laso encode [--version V] [--source N] [--type N] [--listen] [--preamble N] [--sync HEX] [--line-code C] [--packet-interleave L] PAYLOAD_HEX
+  laso decode [--skip N] [--binary] [--line-code C] [--packet-interleave L] [FILE]
+  laso explain [--line-code C] [--packet-interleave L] FRAME_HEX
+  laso json [--skip N] [--binary] [--line-code C] [--packet-interleave L] [FILE]
+  laso pcap [--skip N] [--binary] [--line-code C] [--packet-interleave L] [FILE]
+  laso dissector
+  laso modulate [MODEM] IQ_FILE [FILE]
+  laso demodulate [MODEM] [--sync HEX] [--sync-errors N] [--line-code C] IQ_FILE
+
+Modem: [--format cu8|cs16] [--rate N] [--bitrate N] [--deviation HZ | --ook]
+Line codes: 6b8b, manchester, pn9, none
+Packet interleave: spread, sequential
+Versions: v2, v2short, naked, nakedshort{}",
+        if cfg!(feature = "legacy") {
+            ", legacy"
+        } else {
+            ""
+        }
+    );
+    process::exit(2);
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("laso: {msg}");
+    process::exit(1);
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let s = s.strip_prefix("0x").unwrap_or(&s);
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(h) => u32::from_str_radix(h, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_version(s: &str) -> Option<MessageVersion> {
+    match s.to_ascii_lowercase().as_str() {
+        #[cfg(feature = "legacy")]
+        "legacy" => Some(MessageVersion::LegacyLaso),
+        "v2" => Some(MessageVersion::V2),
+        "v2short" => Some(MessageVersion::V2Short),
+        "naked" => Some(MessageVersion::Naked),
+        "nakedshort" => Some(MessageVersion::NakedShort),
+        _ => None,
+    }
+}
+
+fn parse_line_code(s: &str) -> LineCoding {
+    LineCoding::from_name(s).unwrap_or_else(|| usage())
+}
+
+fn parse_packet_interleave(s: &str) -> PacketInterleave {
+    PacketInterleave::from_name(s).unwrap_or_else(|| usage())
+}
+
+fn encode(args: &[String]) {
+    let mut msg: Message<MAX_MESSAGE> = Message::default();
+    let mut preamble = 0;
+    let mut sync = Vec::new();
+    let mut line_code = LineCoding::default();
+    let mut packet_interleave = PacketInterleave::default();
+    let mut payload = None;
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--version" => msg.version = parse_version(value()).unwrap_or_else(|| usage()),
+            "--source" => msg.source_address = parse_number(value()).unwrap_or_else(|| usage()),
+            "--type" => msg.packet_type = Some(parse_number(value()).unwrap_or_else(|| usage())),
+            "--listen" => msg.will_listen = true,
+            "--preamble" => preamble = value().parse().unwrap_or_else(|_| usage()),
+            "--sync" => sync = parse_hex(value()).unwrap_or_else(|| usage()),
+            "--line-code" => line_code = parse_line_code(value()),
+            "--packet-interleave" => packet_interleave = parse_packet_interleave(value()),
+            _ if payload.is_none() => payload = Some(parse_hex(arg).unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
+    let payload = payload.unwrap_or_default();
+    if msg.data.extend_from_slice(&payload).is_err() {
+        fail(&format!("payload is limited to {MAX_MESSAGE} bytes"));
+    }
+
+    if preamble + sync.len() + line_code.packet_size() > MAX_FRAME_SIZE {
+        fail(&format!(
+            "preamble, sync word and packet are limited to {MAX_FRAME_SIZE} bytes"
+        ));
+    }
+    let framing = Framing::new(preamble, &sync)
+        .with_line_code(line_code)
+        .with_packet_interleave(packet_interleave);
+    for frame in MessageSender::new(msg).frames(framing) {
+        println!("{}", hex(&frame));
+    }
+}
+
+// Hex text with one frame per line, or binary frames back to back.
+// Frames are cut at the packet size of the line code.
+fn read_frames(input: &[u8], skip: usize, binary: bool, line_code: LineCoding) -> Vec<LineCoded> {
+    let size = line_code.packet_size();
+    let frames: Vec<Vec<u8>> = if binary {
+        input.chunks(skip + size).map(|c| c.to_vec()).collect()
+    } else {
+        let text = std::str::from_utf8(input)
+            .unwrap_or_else(|_| fail("input is not hex text, use --binary for binary frames"));
+        text.lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(n, l)| {
+                parse_hex(l).unwrap_or_else(|| fail(&format!("line {}: not a hex frame", n + 1)))
+            })
+            .collect()
+    };
+
+    frames
+        .iter()
+        .map(|f| {
+            let f = f.get(skip..).unwrap_or_default();
+            if f.len() < size {
+                eprintln!("laso: short frame of {} bytes, padded with zeros", f.len());
+            }
+            // Cannot fail, the line code size is at most MAX_RADIO_PACKET_SIZE
+            LineCoded::from_slice(&f[..f.len().min(size)]).unwrap()
+        })
+        .collect()
+}
+
+// Records from the capture file or stdin, other input is read with
+// read_frames and gets no reception metadata
+fn input_records(args: &[String]) -> (Framing<'static>, Vec<CaptureRecord>) {
+    let mut skip = 0;
+    let mut binary = false;
+    let mut framing = Framing::NONE;
+    let mut file = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--skip" => {
+                skip = it
+                    .next()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--binary" => binary = true,
+            "--line-code" => {
+                framing =
+                    framing.with_line_code(parse_line_code(it.next().unwrap_or_else(|| usage())))
+            }
+            "--packet-interleave" => {
+                framing = framing.with_packet_interleave(parse_packet_interleave(
+                    it.next().unwrap_or_else(|| usage()),
+                ))
+            }
+            _ if file.is_none() => file = Some(arg.clone()),
+            _ => usage(),
+        }
+    }
+
+    let input = match &file {
+        Some(path) => fs::read(path).unwrap_or_else(|e| fail(&format!("{path}: {e}"))),
+        None => {
+            let mut buf = Vec::new();
+            io::stdin()
+                .read_to_end(&mut buf)
+                .unwrap_or_else(|e| fail(&e.to_string()));
+            buf
+        }
+    };
+
+    if input.starts_with(CAPTURE_MAGIC) {
+        let records = CaptureReader::new(&input[..])
+            .and_then(|r| r.collect())
+            .unwrap_or_else(|e| fail(&format!("capture: {e}")));
+        return (framing, records);
+    }
+
+    let records = read_frames(&input, skip, binary, framing.line_code)
+        .into_iter()
+        .map(|frame| CaptureRecord {
+            frame,
+            ..Default::default()
+        })
+        .collect();
+    (framing, records)
+}
+
+fn input_frames(args: &[String]) -> (Framing<'static>, Vec<LineCoded>) {
+    let (framing, records) = input_records(args);
+    (framing, records.into_iter().map(|r| r.frame).collect())
+}
+
+// Multi packet V2 messages have no end marker, a receiver uses a timeout.
+// Without timing the message is over when the next frame does not fit.
+fn ends_message(rx: &RxMessageDecoder<MAX_MESSAGE>, dec: &GolayDecoderResult) -> bool {
+    match rx.state() {
+        RxState::Idle => false,
+        RxState::Complete | RxState::Failed(_) => true,
+        RxState::Receiving(_) => rx.message_available() && rx.clone().append(dec).is_err(),
+    }
+}
+
+fn decode(args: &[String]) {
+    let mut rx: RxMessageDecoder<MAX_MESSAGE> = RxMessageDecoder::default();
+    let (framing, frames) = input_frames(args);
+    for (n, frame) in frames.iter().enumerate() {
+        let dec = framing.decode_packet(frame);
+        if ends_message(&rx, &dec) {
+            print_message(&mut rx);
+            rx.reset();
+        }
+
+        println!("frame {n}");
+        println!("  radio        {}", hex(frame));
+        let stripped = framing.decode(frame);
+        println!("  line decoded {}", hex(&stripped.data()));
+        let golay = framing.packet_interleave.deinterleave(&stripped);
+        println!("  deinterleaved {}", hex(&golay.data()));
+
+        for (idx, cw) in dec.codewords.iter().enumerate() {
+            let (word, errors, parity) = PacketWithGolay::undo_golay(*cw);
+            println!(
+                "    {idx}  {cw:06x} -> {word:03x}  {errors} corrected{}",
+                if parity { "" } else { ", parity failed" }
+            );
+        }
+        println!(
+            "  golay        {} corrected, {} parity failures",
+            dec.errors, dec.parity_errors
+        );
+
+        let mut stream = StreamDecoder::for_framing(&framing);
+        stream.push_slice(frame);
+        match rx.peek_header(&mut stream) {
+            Ok(header) => {
+                println!("  status       {:?}", header.status);
+                if let Some(t) = header.packet_type {
+                    println!("  type         {t:#x}");
+                }
+                if let Some(s) = header.source_address {
+                    println!("  source       {s:#x}");
+                }
+            }
+            Err(err) => println!("  status       {:?}", err.status),
+        }
+        println!("  data         {}", hex(&dec.data.data));
+
+        match rx.append(&dec) {
+            Ok(status) => println!("  rx           ok, {status:?}"),
+            Err(err) => {
+                println!("  rx           {:?}", err.kind);
+                if let Some(crc) = err.crc {
+                    println!(
+                        "  crc          expected {:02x}, received {:02x}",
+                        crc.expected, crc.received
+                    );
+                }
+            }
+        }
+    }
+
+    print_message(&mut rx);
+}
+
+fn print_message(rx: &mut RxMessageDecoder<MAX_MESSAGE>) {
+    let state = rx.state();
+    match rx.take_message() {
+        Some(msg) => {
+            println!("message");
+            println!("  version      {:?}", msg.msg.version);
+            println!("  source       {:#x}", msg.msg.source_address);
+            if let Some(t) = msg.msg.packet_type {
+                println!("  type         {t:#x}");
+            }
+            println!("  listens      {}", msg.msg.will_listen);
+            println!("  payload      {}", hex(&msg.msg.data));
+            println!(
+                "  confidence   {:?}, {} corrected bits",
+                msg.confidence, msg.errors
+            );
+        }
+        None if state != RxState::Idle => println!("no message, {state:?}"),
+        None => (),
+    }
+}
+
+fn json(args: &[String]) {
+    let mut rx: RxMessageDecoder<MAX_MESSAGE> = RxMessageDecoder::default();
+    let emit = |rx: &mut RxMessageDecoder<MAX_MESSAGE>| {
+        match rx.state() {
+            RxState::Idle => (),
+            RxState::Failed(kind) => println!("{}", failed_to_json(rx, kind, None)),
+            _ => match rx.take_message() {
+                Some(msg) => println!("{}", to_json(&msg, None, &RawPayload)),
+                // The input ended before the last packet
+                None => println!("{}", failed_to_json(rx, RxErrorKind::Truncated, None)),
+            },
+        }
+        rx.reset();
+    };
+
+    let (framing, frames) = input_frames(args);
+    for frame in frames {
+        let dec = framing.decode_packet(&frame);
+        if ends_message(&rx, &dec) {
+            emit(&mut rx);
+        }
+        // A broken packet fails the message, it is written by emit
+        let _ = rx.append(&dec);
+    }
+    emit(&mut rx);
+}
+
+fn pcap(args: &[String]) {
+    let mut rx: RxMessageDecoder<MAX_MESSAGE> = RxMessageDecoder::default();
+    let mut w =
+        PcapWriter::new(IoSink(io::stdout().lock())).unwrap_or_else(|e| fail(&e.to_string()));
+
+    let (framing, records) = input_records(args);
+    for record in records {
+        let dec = framing.decode_packet(&record.frame);
+        if ends_message(&rx, &dec) {
+            rx.reset();
+        }
+        // The status as decoded in the context of the message
+        let status = match rx.append(&dec) {
+            Ok(status) => status,
+            Err(err) => err.status,
+        };
+        w.write(&record, &dec, status)
+            .unwrap_or_else(|e| fail(&e.to_string()));
+    }
+    w.into_inner()
+        .0
+        .flush()
+        .unwrap_or_else(|e| fail(&e.to_string()));
+}
+
+struct ModemArgs {
+    config: ModemConfig,
+    format: SampleFormat,
+    sync: Vec<u8>,
+    sync_errors: u32,
+    line_code: LineCoding,
+    files: Vec<String>,
+}
+
+fn modem_args(args: &[String]) -> ModemArgs {
+    let mut m = ModemArgs {
+        config: ModemConfig::default(),
+        format: SampleFormat::Cu8,
+        sync: vec![0x2d, 0xd4],
+        sync_errors: 1,
+        line_code: LineCoding::default(),
+        files: Vec::new(),
+    };
+
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--format" => m.format = SampleFormat::from_name(value()).unwrap_or_else(|| usage()),
+            "--rate" => m.config.sample_rate = value().parse().unwrap_or_else(|_| usage()),
+            "--bitrate" => m.config.bitrate = value().parse().unwrap_or_else(|_| usage()),
+            "--deviation" => {
+                m.config.modulation = Modulation::Fsk {
+                    deviation_hz: value().parse().unwrap_or_else(|_| usage()),
+                }
+            }
+            "--ook" => m.config.modulation = Modulation::Ook,
+            "--sync" => m.sync = parse_hex(value()).unwrap_or_else(|| usage()),
+            "--sync-errors" => m.sync_errors = value().parse().unwrap_or_else(|_| usage()),
+            "--line-code" => m.line_code = parse_line_code(value()),
+            _ => m.files.push(arg.clone()),
+        }
+    }
+
+    if m.config.bitrate == 0 || m.config.sample_rate < 2 * m.config.bitrate {
+        fail("the sample rate must be at least twice the bitrate");
+    }
+    if m.sync.is_empty() || m.sync.len() > 8 {
+        fail("the sync word must be 1 to 8 bytes");
+    }
+    m
+}
+
+fn modulate(args: &[String]) {
+    let m = modem_args(args);
+    let (out, input) = match m.files.as_slice() {
+        [out] => (out, None),
+        [out, input] => (out, Some(input)),
+        _ => usage(),
+    };
+
+    let input = match input {
+        Some(path) => fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("{path}: {e}"))),
+        None => io::read_to_string(io::stdin()).unwrap_or_else(|e| fail(&e.to_string())),
+    };
+
+    // A few bits of silence around every frame
+    let gap = (m.config.samples_per_bit() * 16.0) as usize;
+    let mut modulator = Modulator::new(m.config);
+    let mut samples = Vec::new();
+    for line in input.lines().filter(|l| !l.trim().is_empty()) {
+        let frame = parse_hex(line).unwrap_or_else(|| fail(&format!("not hex: {line}")));
+        modulator.silence(gap, &mut samples);
+        modulator.modulate(&frame, &mut samples);
+    }
+    modulator.silence(gap, &mut samples);
+
+    let file = fs::File::create(out).unwrap_or_else(|e| fail(&format!("{out}: {e}")));
+    write_iq(io::BufWriter::new(file), m.format, &samples)
+        .unwrap_or_else(|e| fail(&format!("{out}: {e}")));
+}
+
+fn demodulate(args: &[String]) {
+    let m = modem_args(args);
+    let [input] = m.files.as_slice() else { usage() };
+
+    let file = fs::File::open(input).unwrap_or_else(|e| fail(&format!("{input}: {e}")));
+    let samples = read_iq(io::BufReader::new(file), m.format)
+        .unwrap_or_else(|e| fail(&format!("{input}: {e}")));
+
+    let mut sync = FrameSync::new(&m.sync, m.sync_errors).with_line_code(m.line_code);
+    for packet in Demodulator::new(m.config).receive(&samples, &mut sync) {
+        println!("{}", hex(&packet));
+    }
+}
+
+// Where a data bit of the 12 bit word ends up in the 12 byte packet
+fn word_bit_to_packet(word: usize, bit: usize) -> (usize, usize) {
+    let first = word / 2 * 3;
+    match (word % 2, bit) {
+        (0, 4..) => (first, bit - 4),
+        (0, _) => (first + 1, bit + 4),
+        (_, 8..) => (first + 1, bit - 8),
+        _ => (first + 2, bit),
+    }
+}
+
+// Position of a radio bit in the line decoded packet, byte * 8 + bit
+// with bit 0 the LSb, or what the radio bit is for when it carries no data
+fn line_decoded_bit(line_code: LineCoding, r: usize, bit: usize) -> Result<usize, &'static str> {
+    // Radio bit positions of the six data bits, a b X c d Y e f
+    const DATA_BITS: [(usize, usize); 6] = [(7, 5), (6, 4), (4, 3), (3, 2), (1, 1), (0, 0)];
+
+    match line_code {
+        // 6 bit chunks are consumed LSb first
+        LineCoding::Dc6b8b => DATA_BITS
+            .iter()
+            .find(|(b, _)| *b == bit)
+            .map(|&(_, chunk_bit)| r * 6 + chunk_bit)
+            .ok_or("dc balance"),
+        // The first half of every pair, high nibble first
+        LineCoding::Manchester if bit % 2 == 1 => {
+            let nibble = if r % 2 == 0 { 4 } else { 0 };
+            Ok(r / 2 * 8 + nibble + bit / 2)
+        }
+        LineCoding::Manchester => Err("manchester second half"),
+        LineCoding::Pn9 | LineCoding::Identity => Ok(r * 8 + bit),
+    }
+}
+
+// Codeword and codeword bit of a line decoded bit, see line_decoded_bit
+fn codeword_bit(packet_interleave: PacketInterleave, s: usize) -> (usize, usize) {
+    let (byte, bit) = (s / 8, s % 8);
+    match packet_interleave {
+        // Bit N of every codeword in byte N, codeword 0 in the MSb
+        PacketInterleave::Spread => (7 - bit, byte),
+        // Three bytes per codeword, MSB first
+        PacketInterleave::Sequential => (byte / 3, (2 - byte % 3) * 8 + bit),
+    }
+}
+
+fn explain(args: &[String]) {
+    let mut framing = Framing::NONE;
+    let mut frame = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--line-code" => framing = framing.with_line_code(parse_line_code(value())),
+            "--packet-interleave" => {
+                framing = framing.with_packet_interleave(parse_packet_interleave(value()))
+            }
+            _ if frame.is_none() => frame = Some(arg),
+            _ => usage(),
+        }
+    }
+    let line_code = framing.line_code;
+    let data = parse_hex(frame.unwrap_or_else(|| usage())).unwrap_or_else(|| usage());
+    let size = line_code.packet_size();
+    if data.len() != size {
+        fail(&format!("frame has {} bytes, expected {size}", data.len()));
+    }
+
+    let dec = framing.decode_packet(&data);
+    let mut corrected = [0u32; CODEWORDS];
+    for (c, cw) in corrected.iter_mut().zip(dec.codewords.iter()) {
+        *c = PacketWithGolay::apply_golay(PacketWithGolay::undo_golay(*cw).0);
+    }
+
+    for (r, byte) in data.iter().enumerate() {
+        println!("byte {r:2} {byte:02x}");
+        for bit in (0..8).rev() {
+            let value = (byte >> bit) & 1;
+            let s = match line_decoded_bit(line_code, r, bit) {
+                Ok(s) => s,
+                Err(role) => {
+                    println!("  bit {bit} = {value}  {role}");
+                    continue;
+                }
+            };
+
+            let (cw, n) = codeword_bit(framing.packet_interleave, s);
+            let flag = if (dec.codewords[cw] ^ corrected[cw]) >> n & 1 != 0 {
+                "  corrected"
+            } else {
+                ""
+            };
+
+            let role = match n {
+                0..=11 => {
+                    let (pb, pbit) = word_bit_to_packet(cw, n);
+                    let target = if pb == 11 {
+                        format!("status bit {pbit}")
+                    } else {
+                        format!("data byte {pb} bit {pbit}")
+                    };
+                    format!("word bit {n:2} -> {target}")
+                }
+                12..=22 => format!("check bit {}", n - 12),
+                _ => "parity".to_string(),
+            };
+            println!("  bit {bit} = {value}  codeword {cw} bit {n:2}  {role}{flag}");
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some((cmd, rest)) = args.split_first() else {
+        usage()
+    };
+
+    match cmd.as_str() {
+        "encode" => encode(rest),
+        "decode" => decode(rest),
+        "explain" => explain(rest),
+        "json" => json(rest),
+        "pcap" => pcap(rest),
+        "dissector" => print!("{}", lua_dissector()),
+        "modulate" => modulate(rest),
+        "demodulate" => demodulate(rest),
+        _ => usage(),
+    }
+}
@@ -0,0 +1,431 @@
+// Capture files of received radio packets, for reproducing field
+// issues offline.
+//
+// A capture starts with an 8 byte header, the magic "LASOCAP" and the
+// format version, followed by fixed size records. All integers are
+// little endian.
+//
+//   offset  size  field
+//        0     8  timestamp_us
+//        8     4  frequency_hz
+//       12     4  gateway_id
+//       16     1  rssi
+//       17     1  lna
+//       18     1  frame length, at most 48
+//       19    48  frame, zero padded after the length
+//
+// Version 1 files have the same layout with a 32 byte frame, they are
+// still read.
+//
+// Frames are stored as the radio delivered them, truncated packets
+// included. The writer works on any ByteSink and does not need std, the
+// reader is only available with std. Replay feeds a capture through the
+// link layer the same way a live RadioRx does.
+
+use core::iter::Peekable;
+
+use heapless::Vec;
+use ufmt::derive::uDebug;
+
+use crate::link::{RadioRx, RxPacket};
+use crate::packet::MAX_RADIO_PACKET_SIZE;
+
+pub const CAPTURE_MAGIC: &[u8; 7] = b"LASOCAP";
+// Version 2 widened the frame from 32 to 48 bytes for the Manchester
+// line code
+pub const CAPTURE_VERSION: u8 = 2;
+pub const HEADER_SIZE: usize = 8;
+pub const RECORD_SIZE: usize = 19 + MAX_RADIO_PACKET_SIZE;
+pub const RECORD_SIZE_V1: usize = 19 + 32;
+
+// Record size of a supported format version
+pub const fn record_size(version: u8) -> Option<usize> {
+    match version {
+        1 => Some(RECORD_SIZE_V1),
+        CAPTURE_VERSION => Some(RECORD_SIZE),
+        _ => None,
+    }
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadFrameLength(u8),
+}
+
+// A single received packet with its reception metadata
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub timestamp_us: u64,
+    pub frequency_hz: u32,
+    pub gateway_id: u32,
+    pub rssi: u8,
+    pub lna: u8,
+    pub frame: Vec<u8, MAX_RADIO_PACKET_SIZE>,
+}
+
+impl CaptureRecord {
+    pub fn from_rx(
+        packet: &RxPacket,
+        timestamp_us: u64,
+        frequency_hz: u32,
+        gateway_id: u32,
+    ) -> Self {
+        Self {
+            timestamp_us,
+            frequency_hz,
+            gateway_id,
+            rssi: packet.rssi,
+            lna: packet.lna,
+            frame: packet.packet.clone(),
+        }
+    }
+
+    // The packet as the radio delivered it
+    pub fn to_rx(&self) -> RxPacket {
+        RxPacket {
+            packet: self.frame.clone(),
+            lna: self.lna,
+            rssi: self.rssi,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut out = [0; RECORD_SIZE];
+        out[0..8].copy_from_slice(&self.timestamp_us.to_le_bytes());
+        out[8..12].copy_from_slice(&self.frequency_hz.to_le_bytes());
+        out[12..16].copy_from_slice(&self.gateway_id.to_le_bytes());
+        out[16] = self.rssi;
+        out[17] = self.lna;
+        out[18] = self.frame.len() as u8;
+        out[19..19 + self.frame.len()].copy_from_slice(&self.frame);
+        out
+    }
+
+    pub fn decode(data: &[u8; RECORD_SIZE]) -> Result<Self, CaptureError> {
+        Self::decode_slice(data)
+    }
+
+    // Record of a version 1 capture
+    pub fn decode_v1(data: &[u8; RECORD_SIZE_V1]) -> Result<Self, CaptureError> {
+        Self::decode_slice(data)
+    }
+
+    // The frame area is the rest of the record
+    fn decode_slice(data: &[u8]) -> Result<Self, CaptureError> {
+        let len = data[18];
+        let frame = data[19..]
+            .get(..len as usize)
+            .ok_or(CaptureError::BadFrameLength(len))?;
+
+        Ok(Self {
+            timestamp_us: u64::from_le_bytes(data[0..8].try_into().unwrap()),
+            frequency_hz: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            gateway_id: u32::from_le_bytes(data[12..16].try_into().unwrap()),
+            rssi: data[16],
+            lna: data[17],
+            // Cannot fail, the length was checked above
+            frame: Vec::from_slice(frame).unwrap(),
+        })
+    }
+}
+
+pub fn header() -> [u8; HEADER_SIZE] {
+    let mut out = [0; HEADER_SIZE];
+    out[..7].copy_from_slice(CAPTURE_MAGIC);
+    out[7] = CAPTURE_VERSION;
+    out
+}
+
+// Returns the format version
+pub fn check_header(data: &[u8; HEADER_SIZE]) -> Result<u8, CaptureError> {
+    if &data[..7] != CAPTURE_MAGIC {
+        return Err(CaptureError::BadMagic);
+    }
+    match record_size(data[7]) {
+        Some(_) => Ok(data[7]),
+        None => Err(CaptureError::UnsupportedVersion(data[7])),
+    }
+}
+
+// Destination of a capture, a file, flash region or serial port
+pub trait ByteSink {
+    type Error;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+// Fails when the capture does not fit
+impl<const N: usize> ByteSink for Vec<u8, N> {
+    type Error = ();
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), ()> {
+        self.extend_from_slice(data)
+    }
+}
+
+// Any std::io::Write as sink
+#[cfg(feature = "std")]
+pub struct IoSink<W: std::io::Write>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> ByteSink for IoSink<W> {
+    type Error = std::io::Error;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write_all(data)
+    }
+}
+
+pub struct CaptureWriter<S: ByteSink> {
+    sink: S,
+}
+
+impl<S: ByteSink> CaptureWriter<S> {
+    // Start a new capture, writes the header
+    pub fn new(mut sink: S) -> Result<Self, S::Error> {
+        sink.write_all(&header())?;
+        Ok(Self { sink })
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> Result<(), S::Error> {
+        self.sink.write_all(&record.encode())
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+#[cfg(feature = "std")]
+pub use reader::CaptureReader;
+
+#[cfg(feature = "std")]
+mod reader {
+    use std::io::{self, Read};
+
+    use super::*;
+
+    impl From<CaptureError> for io::Error {
+        fn from(e: CaptureError) -> Self {
+            io::Error::new(io::ErrorKind::InvalidData, std::format!("{e:?}"))
+        }
+    }
+
+    // Iterates over the records of a capture. A record cut short at
+    // the end of the file is an UnexpectedEof error.
+    pub struct CaptureReader<R: Read> {
+        inner: R,
+        record_size: usize,
+        done: bool,
+    }
+
+    impl<R: Read> CaptureReader<R> {
+        // Reads and checks the header
+        pub fn new(mut inner: R) -> io::Result<Self> {
+            let mut header = [0; HEADER_SIZE];
+            inner.read_exact(&mut header)?;
+            let version = check_header(&header)?;
+            Ok(Self {
+                inner,
+                // Cannot fail, check_header accepts supported versions only
+                record_size: record_size(version).unwrap(),
+                done: false,
+            })
+        }
+
+        fn read_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+            let mut data = [0; RECORD_SIZE];
+            let data = &mut data[..self.record_size];
+            let mut read = 0;
+            while read < data.len() {
+                match self.inner.read(&mut data[read..]) {
+                    Ok(0) if read == 0 => return Ok(None),
+                    Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(n) => read += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(Some(CaptureRecord::decode_slice(data)?))
+        }
+    }
+
+    impl<R: Read> Iterator for CaptureReader<R> {
+        type Item = io::Result<CaptureRecord>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.done {
+                return None;
+            }
+            let res = self.read_record().transpose();
+            // Stop after the end or the first error
+            self.done = !matches!(res, Some(Ok(_)));
+            res
+        }
+    }
+}
+
+// Replays recorded packets as a RadioRx. Time only moves through the
+// receive timeouts and the record timestamps, so message boundaries
+// come out the same as during the live reception.
+pub struct Replay<I: Iterator> {
+    records: Peekable<I>,
+    now_us: Option<u64>,
+    last: Option<CaptureRecord>,
+}
+
+impl<I, E> Replay<I>
+where
+    I: Iterator<Item = Result<CaptureRecord, E>>,
+{
+    pub fn new(records: I) -> Self {
+        Self {
+            records: records.peekable(),
+            now_us: None,
+            last: None,
+        }
+    }
+
+    // Time of the capture clock, starts at the first record
+    pub fn now_us(&self) -> Option<u64> {
+        self.now_us
+    }
+
+    // The record of the last received packet, for the frequency
+    // and gateway it was received on
+    pub fn last_record(&self) -> Option<&CaptureRecord> {
+        self.last.as_ref()
+    }
+
+    pub fn finished(&mut self) -> bool {
+        self.records.peek().is_none()
+    }
+}
+
+impl<I, E> RadioRx for Replay<I>
+where
+    I: Iterator<Item = Result<CaptureRecord, E>>,
+{
+    type Error = E;
+
+    async fn receive(&mut self, packet: &mut RxPacket, timeout_us: u32) -> Result<bool, E> {
+        if let Some(Err(e)) = self.records.next_if(Result::is_err) {
+            return Err(e);
+        }
+        let Some(Ok(next)) = self.records.peek() else {
+            return Ok(false);
+        };
+
+        let at = next.timestamp_us;
+        let now = *self.now_us.get_or_insert(at);
+        if at.saturating_sub(now) > timeout_us as u64 {
+            self.now_us = Some(now + timeout_us as u64);
+            return Ok(false);
+        }
+
+        let Some(Ok(record)) = self.records.next() else {
+            return Ok(false);
+        };
+        self.now_us = Some(now.max(at));
+        *packet = record.to_rx();
+        self.last = Some(record);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_record() -> CaptureRecord {
+        CaptureRecord {
+            timestamp_us: 0x0102_0304_0506_0708,
+            frequency_hz: 868_300_000,
+            gateway_id: 0x1234,
+            rssi: 0x40,
+            lna: 2,
+            frame: Vec::from_slice(&[0x5a; 20]).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let r = test_record();
+        let data = r.encode();
+        assert_eq!(&data[..8], &[8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(data[18], 20);
+        assert_eq!(CaptureRecord::decode(&data), Ok(r));
+
+        let mut bad = data;
+        bad[18] = 49;
+        assert_eq!(
+            CaptureRecord::decode(&bad),
+            Err(CaptureError::BadFrameLength(49))
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(check_header(&header()), Ok(CAPTURE_VERSION));
+        let mut h = header();
+        h[7] = 9;
+        assert_eq!(check_header(&h), Err(CaptureError::UnsupportedVersion(9)));
+        h[0] = b'X';
+        assert_eq!(check_header(&h), Err(CaptureError::BadMagic));
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut w = CaptureWriter::new(Vec::<u8, 120>::new()).unwrap();
+        w.write(&test_record()).unwrap();
+        // Full
+        assert_eq!(w.write(&test_record()), Err(()));
+        let data = w.into_inner();
+        assert_eq!(data.len(), HEADER_SIZE + RECORD_SIZE);
+        assert_eq!(&data[..HEADER_SIZE], b"LASOCAP\x02");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reader() {
+        let mut w = CaptureWriter::new(IoSink(std::vec::Vec::new())).unwrap();
+        w.write(&test_record()).unwrap();
+        w.write(&CaptureRecord::default()).unwrap();
+        let data = w.into_inner().0;
+
+        let records: std::vec::Vec<_> = CaptureReader::new(&data[..])
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records, [test_record(), CaptureRecord::default()]);
+
+        // Cut inside the second record
+        let mut reader = CaptureReader::new(&data[..data.len() - 1]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(reader.next().is_none());
+
+        assert!(CaptureReader::new(&b"PCAPFILE"[..]).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reader_v1() {
+        let r = test_record();
+        let mut data = std::vec::Vec::from(&b"LASOCAP\x01"[..]);
+        data.extend_from_slice(&r.encode()[..RECORD_SIZE_V1]);
+        let mut bad = r.encode();
+        bad[18] = 33;
+        data.extend_from_slice(&bad[..RECORD_SIZE_V1]);
+
+        let mut reader = CaptureReader::new(&data[..]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), r);
+        // Longer than the version 1 frame
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,313 @@
+// Diversity combining of multiple received copies of the same packet.
+//
+// A repeated packet, or a packet heard by several gateways, arrives as
+// a number of independently corrupted copies. The copies are combined
+// bit by bit before the Golay decoding. Each copy votes for every bit
+// with its weight. Equal weights give a plain majority vote, weights
+// derived from the signal quality (RSSI, link margin) give a soft
+// combining of the copies.
+//
+// Copies are combined before the line decoding, all copies have to use
+// the line code and packet layout of the combiner, see for_framing.
+// add, add_weighted and packet take 6b/8b coded packets and only work for
+// 6b/8b combiners, other line codes use add_coded and coded.
+//
+// Every copy is also decoded on its own to find out how many codewords
+// were rescued by the combining. A codeword is rescued when no copy
+// decoded it to the combined value without a parity failure.
+
+use heapless::Vec;
+
+use crate::frame::Framing;
+use crate::interleave::PacketInterleave;
+use crate::line::{LineCode as _, LineCoded, LineCoding};
+use crate::packet::{GolayDecoderResult, PacketWithoutDC};
+use crate::rx::{RxDecodeError, RxErrorKind};
+use crate::stream::{StreamDecoder, CODEWORDS};
+
+#[derive(Clone, Debug, Default)]
+pub struct DiversityCombiner<const N: usize> {
+    line_code: LineCoding,
+    packet_interleave: PacketInterleave,
+    // Received copies together with their weight
+    copies: Vec<(u8, LineCoded), N>,
+}
+
+#[derive(Clone, Debug)]
+pub struct CombinedPacket {
+    pub packet: GolayDecoderResult,
+    // Number of combined copies
+    pub copies: usize,
+    // Codewords that no single copy decoded correctly on its own
+    pub rescued: usize,
+    // Codewords that still fail the parity check after combining
+    pub failed: usize,
+}
+
+impl<const N: usize> DiversityCombiner<N> {
+    pub fn new() -> Self {
+        Self::with_line_code(LineCoding::Dc6b8b)
+    }
+
+    pub fn with_line_code(line_code: LineCoding) -> Self {
+        Self {
+            line_code,
+            packet_interleave: PacketInterleave::Spread,
+            copies: Vec::new(),
+        }
+    }
+
+    // Combiner for packets sent with the line code and packet layout
+    // of the framing
+    pub fn for_framing(framing: &Framing) -> Self {
+        Self {
+            packet_interleave: framing.packet_interleave,
+            ..Self::with_line_code(framing.line_code)
+        }
+    }
+
+    // Prepare for the next packet
+    pub fn reset(&mut self) {
+        self.copies.clear();
+    }
+
+    pub fn copies(&self) -> usize {
+        self.copies.len()
+    }
+
+    // Add a 6b/8b coded copy for majority voting
+    pub fn add(&mut self, p: &PacketWithoutDC) -> Result<(), RxDecodeError> {
+        self.add_weighted(p, 1)
+    }
+
+    // Add a 6b/8b coded copy with the given confidence, copies with zero
+    // weight are ignored. Fails with Invalid for other line codes.
+    pub fn add_weighted(&mut self, p: &PacketWithoutDC, weight: u8) -> Result<(), RxDecodeError> {
+        if self.line_code != LineCoding::Dc6b8b {
+            return Err(RxErrorKind::Invalid.into());
+        }
+        self.add_coded(&p.data(), weight)
+    }
+
+    // Add a copy as received, coded with the line code of the combiner.
+    // Missing bytes at the end count as zeros, extra bytes are ignored.
+    pub fn add_coded(&mut self, data: &[u8], weight: u8) -> Result<(), RxDecodeError> {
+        if weight == 0 {
+            return Ok(());
+        }
+
+        let mut copy = LineCoded::new();
+        let len = data.len().min(self.line_code.size());
+        // Cannot fail, the line code size is at most MAX_RADIO_PACKET_SIZE
+        copy.extend_from_slice(&data[..len]).unwrap();
+        copy.resize(self.line_code.size(), 0).unwrap();
+        self.copies
+            .push((weight, copy))
+            .map_err(|_| RxErrorKind::Full.into())
+    }
+
+    // The combined line coded packet, or None when no copy was added yet
+    pub fn coded(&self) -> Option<LineCoded> {
+        // Ties are broken by the first copy with the highest weight
+        let best = &self
+            .copies
+            .iter()
+            .rev()
+            .max_by_key(|(weight, _)| *weight)?
+            .1;
+
+        let mut data = best.clone();
+        data.fill(0);
+        for (idx, b) in data.iter_mut().enumerate() {
+            for bit in 0..8 {
+                let mask = 1 << bit;
+
+                let mut vote = 0_i32;
+                for (weight, copy) in &self.copies {
+                    if copy[idx] & mask != 0 {
+                        vote += *weight as i32;
+                    } else {
+                        vote -= *weight as i32;
+                    }
+                }
+
+                if vote > 0 || (vote == 0 && best[idx] & mask != 0) {
+                    *b |= mask;
+                }
+            }
+        }
+
+        Some(data)
+    }
+
+    // The combined packet of a 6b/8b combiner, None for other line codes
+    pub fn packet(&self) -> Option<PacketWithoutDC> {
+        if self.line_code != LineCoding::Dc6b8b {
+            return None;
+        }
+        self.coded().map(|data| PacketWithoutDC::new(&data))
+    }
+
+    fn decoder(&self, data: &[u8]) -> StreamDecoder {
+        let mut stream = StreamDecoder::with_line_code(self.line_code)
+            .with_packet_interleave(self.packet_interleave);
+        stream.push_slice(data);
+        stream
+    }
+
+    // Decode the combined packet and compare it with the single copies
+    pub fn result(&self) -> Option<CombinedPacket> {
+        let mut combined = self.decoder(&self.coded()?);
+        let packet = combined.result()?;
+
+        // Codewords at least one copy got right on its own
+        let mut single_ok = 0_u8;
+        for (_, copy) in &self.copies {
+            let mut single = self.decoder(copy);
+            for cw in 0..CODEWORDS {
+                if !single.parity_failed(cw) && single.word(cw) == combined.word(cw) {
+                    single_ok |= 1 << cw;
+                }
+            }
+        }
+
+        let mut rescued = 0;
+        let mut failed = 0;
+        for cw in 0..CODEWORDS {
+            if combined.parity_failed(cw) {
+                failed += 1;
+            } else if single_ok & (1 << cw) == 0 {
+                rescued += 1;
+            }
+        }
+
+        Some(CombinedPacket {
+            packet,
+            copies: self.copies.len(),
+            rescued,
+            failed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::{test_v2_packet, PacketData, TEST_DATA};
+
+    fn test_packet() -> (PacketData, PacketWithoutDC) {
+        let packet = test_v2_packet(&TEST_DATA);
+        let radio = packet.encode_for_transmit();
+        (packet, radio)
+    }
+
+    #[test]
+    fn test_empty() {
+        let combiner: DiversityCombiner<3> = DiversityCombiner::new();
+        assert!(combiner.packet().is_none());
+        assert!(combiner.result().is_none());
+    }
+
+    #[test]
+    fn test_majority_rescue() {
+        let (packet, radio) = test_packet();
+
+        // Three copies, each with a long burst in a different place
+        let mut combiner: DiversityCombiner<3> = DiversityCombiner::new();
+        for burst in [0, 10, 20] {
+            let mut data = radio.data();
+            data[burst..burst + 10].iter_mut().for_each(|b| *b = !*b);
+
+            let copy = PacketWithoutDC::new(&data);
+            assert_ne!(copy.decode().data.data, packet.data);
+            combiner.add(&copy).unwrap();
+        }
+
+        assert_eq!(combiner.packet(), Some(radio));
+
+        let res = combiner.result().unwrap();
+        assert_eq!(res.copies, 3);
+        assert_eq!(res.failed, 0);
+        assert!(res.rescued > 0);
+        assert_eq!(res.packet.data.data, packet.data);
+        assert_eq!(res.packet.errors, 0);
+    }
+
+    #[test]
+    fn test_manchester() {
+        let packet = test_v2_packet(&TEST_DATA);
+        let radio = LineCoding::Manchester.encode_packet(&packet);
+
+        let mut combiner: DiversityCombiner<3> =
+            DiversityCombiner::with_line_code(LineCoding::Manchester);
+        for burst in [0, 15, 30] {
+            let mut copy = radio.clone();
+            copy[burst..burst + 15].iter_mut().for_each(|b| *b = !*b);
+            combiner.add_coded(&copy, 1).unwrap();
+        }
+        assert_eq!(combiner.coded(), Some(radio));
+        assert_eq!(combiner.packet(), None);
+        assert_eq!(
+            combiner.add(&test_packet().1).err().map(|err| err.kind),
+            Some(RxErrorKind::Invalid)
+        );
+
+        let res = combiner.result().unwrap();
+        assert_eq!(res.failed, 0);
+        assert!(res.rescued > 0);
+        assert_eq!(res.packet.data.data, packet.data);
+    }
+
+    #[test]
+    fn test_sequential() {
+        let packet = test_v2_packet(&TEST_DATA);
+        let framing = Framing::NONE
+            .with_line_code(LineCoding::Pn9)
+            .with_packet_interleave(PacketInterleave::Sequential);
+        let radio = framing.encode_packet(&packet);
+
+        // Each copy loses a different codeword
+        let mut combiner: DiversityCombiner<3> = DiversityCombiner::for_framing(&framing);
+        for burst in [0, 6, 12] {
+            let mut copy = radio.clone();
+            copy[burst..burst + 3].iter_mut().for_each(|b| *b = !*b);
+            combiner.add_coded(&copy, 1).unwrap();
+        }
+        assert_eq!(combiner.coded(), Some(radio));
+
+        let res = combiner.result().unwrap();
+        assert_eq!(res.failed, 0);
+        assert_eq!(res.packet.data.data, packet.data);
+    }
+
+    #[test]
+    fn test_weighted_tie_break() {
+        let (_, radio) = test_packet();
+
+        let mut data = radio.data();
+        data.iter_mut().for_each(|b| *b = !*b);
+        let inverted = PacketWithoutDC::new(&data);
+
+        // Equal votes, the copy with higher weight wins the tie
+        let mut combiner: DiversityCombiner<3> = DiversityCombiner::new();
+        combiner.add_weighted(&inverted, 1).unwrap();
+        combiner.add_weighted(&radio, 2).unwrap();
+        combiner.add_weighted(&inverted, 1).unwrap();
+        assert_eq!(combiner.packet(), Some(radio));
+
+        // Stronger copy outweighs the weaker ones
+        combiner.reset();
+        combiner.add_weighted(&inverted, 1).unwrap();
+        combiner.add_weighted(&radio, 3).unwrap();
+        combiner.add_weighted(&inverted, 1).unwrap();
+        combiner.add_weighted(&inverted, 0).unwrap();
+        assert_eq!(combiner.packet(), Some(radio));
+        assert_eq!(combiner.copies(), 3);
+
+        assert_eq!(
+            combiner.add(&radio).err().map(|err| err.kind),
+            Some(RxErrorKind::Full),
+            "Combiner should be full"
+        );
+    }
+}
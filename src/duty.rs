@@ -0,0 +1,360 @@
+// Duty-cycle accounting for the EU SRD sub-bands (ETSI EN 300 220).
+//
+// The transmit time of each band is tracked over a rolling one hour
+// window. The window is split into one minute buckets, a transmission
+// stays accounted for until the end of its bucket is an hour old, up to
+// a minute longer than the regulation requires. This needs no allocation
+// and a fixed amount of memory per band.
+//
+// Time is supplied by the caller as a monotonic millisecond counter.
+
+use ufmt::derive::uDebug;
+
+use crate::plan::TxPlan;
+
+// Length of the rolling window
+pub const WINDOW_MS: u64 = 3_600_000;
+
+const BUCKETS: usize = 60;
+const BUCKET_MS: u64 = WINDOW_MS / BUCKETS as u64;
+// The bucket of the oldest part of the window is kept as well
+const SLOTS: usize = BUCKETS + 1;
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Band {
+    pub min_hz: u32,
+    pub max_hz: u32,
+    // Allowed transmit time in 1/1000 of the window
+    pub duty_cycle_permille: u16,
+}
+
+impl Band {
+    pub const fn new(min_hz: u32, max_hz: u32, duty_cycle_permille: u16) -> Self {
+        Self {
+            min_hz,
+            max_hz,
+            duty_cycle_permille,
+        }
+    }
+
+    pub const fn contains(&self, freq_hz: u32) -> bool {
+        freq_hz >= self.min_hz && freq_hz <= self.max_hz
+    }
+
+    // Transmit time allowed within the window in microseconds
+    pub const fn budget_us(&self) -> u64 {
+        WINDOW_MS * 1000 * self.duty_cycle_permille as u64 / 1000
+    }
+}
+
+// EU 868 MHz sub-bands, 1 %
+pub const EU868_G: Band = Band::new(868_000_000, 868_600_000, 10);
+// 0.1 %
+pub const EU868_G1: Band = Band::new(868_700_000, 869_200_000, 1);
+// 10 %
+pub const EU868_G2: Band = Band::new(869_400_000, 869_650_000, 100);
+// 1 %
+pub const EU868_G3: Band = Band::new(869_700_000, 870_000_000, 10);
+// EU 433 MHz, 10 %
+pub const EU433: Band = Band::new(433_050_000, 434_790_000, 100);
+
+pub const EU_BANDS: [Band; 5] = [EU868_G, EU868_G1, EU868_G2, EU868_G3, EU433];
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DutyCycleError {
+    // The frequency is not part of any configured band
+    UnknownBand,
+    // The transmission does not fit into the budget even with an empty window
+    TooLong,
+    // Enough budget will be available at the given time
+    Wait { until_ms: u64 },
+    // All attempts of a Retries budget are used
+    Exhausted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Usage {
+    // Transmit time in microseconds per bucket
+    buckets: [u32; SLOTS],
+    // Absolute index of the newest bucket
+    current: u64,
+}
+
+impl Usage {
+    const fn new() -> Self {
+        Self {
+            buckets: [0; SLOTS],
+            current: 0,
+        }
+    }
+
+    // Drop the buckets that left the window, bucket k leaves once
+    // (k + 1) * BUCKET_MS + WINDOW_MS has passed
+    fn advance(&mut self, now_ms: u64) {
+        let bucket = now_ms / BUCKET_MS;
+        if bucket <= self.current {
+            return;
+        }
+
+        let expired = (bucket - self.current).min(SLOTS as u64);
+        for n in 1..=expired {
+            self.buckets[((self.current + n) % SLOTS as u64) as usize] = 0;
+        }
+        self.current = bucket;
+    }
+
+    fn used_us(&self) -> u64 {
+        self.buckets.iter().map(|b| *b as u64).sum()
+    }
+
+    // Oldest bucket first
+    fn bucket(&self, age: usize) -> u64 {
+        let idx = (self.current + 1 + age as u64) % SLOTS as u64;
+        self.buckets[idx as usize] as u64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DutyCycleLimiter<const B: usize> {
+    bands: [Band; B],
+    usage: [Usage; B],
+}
+
+impl<const B: usize> DutyCycleLimiter<B> {
+    pub const fn new(bands: [Band; B]) -> Self {
+        Self {
+            bands,
+            usage: [Usage::new(); B],
+        }
+    }
+
+    pub fn band(&self, freq_hz: u32) -> Option<usize> {
+        self.bands.iter().position(|b| b.contains(freq_hz))
+    }
+
+    // Transmit time used within the window ending at now_ms
+    pub fn used_us(&mut self, freq_hz: u32, now_ms: u64) -> Result<u64, DutyCycleError> {
+        let band = self.band(freq_hz).ok_or(DutyCycleError::UnknownBand)?;
+        self.usage[band].advance(now_ms);
+        Ok(self.usage[band].used_us())
+    }
+
+    pub fn available_us(&mut self, freq_hz: u32, now_ms: u64) -> Result<u64, DutyCycleError> {
+        let band = self.band(freq_hz).ok_or(DutyCycleError::UnknownBand)?;
+        let used = self.used_us(freq_hz, now_ms)?;
+        Ok(self.bands[band].budget_us().saturating_sub(used))
+    }
+
+    // Check whether airtime_us can be sent now, without recording it.
+    // When it can't, the error tells when enough budget will be available.
+    pub fn check(
+        &mut self,
+        freq_hz: u32,
+        airtime_us: u64,
+        now_ms: u64,
+    ) -> Result<(), DutyCycleError> {
+        let band = self.band(freq_hz).ok_or(DutyCycleError::UnknownBand)?;
+        let budget = self.bands[band].budget_us();
+        if airtime_us > budget {
+            return Err(DutyCycleError::TooLong);
+        }
+
+        let usage = &mut self.usage[band];
+        usage.advance(now_ms);
+        let mut used = usage.used_us();
+        if used + airtime_us <= budget {
+            return Ok(());
+        }
+
+        // Release the oldest buckets until the transmission fits, the
+        // bucket of age a leaves at the start of bucket current + 1 + a
+        for age in 0..SLOTS {
+            used -= usage.bucket(age);
+            if used + airtime_us <= budget {
+                return Err(DutyCycleError::Wait {
+                    until_ms: (usage.current + 1 + age as u64) * BUCKET_MS,
+                });
+            }
+        }
+
+        // Not reachable, the whole window is released in the last step
+        Err(DutyCycleError::TooLong)
+    }
+
+    // Account for a transmission that already happened
+    pub fn record(&mut self, freq_hz: u32, airtime_us: u64, now_ms: u64) {
+        if let Some(band) = self.band(freq_hz) {
+            let usage = &mut self.usage[band];
+            usage.advance(now_ms);
+            let bucket = &mut usage.buckets[(usage.current % SLOTS as u64) as usize];
+            *bucket = bucket.saturating_add(airtime_us.min(u32::MAX as u64) as u32);
+        }
+    }
+
+    // Check and record in one step, use right before transmitting
+    pub fn try_send(
+        &mut self,
+        freq_hz: u32,
+        airtime_us: u64,
+        now_ms: u64,
+    ) -> Result<(), DutyCycleError> {
+        self.check(freq_hz, airtime_us, now_ms)?;
+        self.record(freq_hz, airtime_us, now_ms);
+        Ok(())
+    }
+
+    pub fn try_send_plan(
+        &mut self,
+        freq_hz: u32,
+        plan: &TxPlan,
+        now_ms: u64,
+    ) -> Result<(), DutyCycleError> {
+        self.try_send(freq_hz, plan.airtime_us, now_ms)
+    }
+
+    // Start a message that may be retransmitted. The first attempt is only
+    // allowed when the budget covers all attempts, every attempt must
+    // then go through Retries::attempt.
+    pub fn retries(
+        &mut self,
+        freq_hz: u32,
+        airtime_us: u64,
+        attempts: u8,
+        now_ms: u64,
+    ) -> Result<Retries, DutyCycleError> {
+        self.check(freq_hz, airtime_us.saturating_mul(attempts as u64), now_ms)?;
+        Ok(Retries {
+            freq_hz,
+            airtime_us,
+            remaining: attempts,
+        })
+    }
+}
+
+// Retransmission budget of a single message, see DutyCycleLimiter::retries
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retries {
+    freq_hz: u32,
+    airtime_us: u64,
+    remaining: u8,
+}
+
+impl Retries {
+    pub fn remaining(&self) -> u8 {
+        self.remaining
+    }
+
+    // Record the next attempt. Fails when no attempts are left or when
+    // other traffic used up the budget in the meantime.
+    pub fn attempt<const B: usize>(
+        &mut self,
+        limiter: &mut DutyCycleLimiter<B>,
+        now_ms: u64,
+    ) -> Result<(), DutyCycleError> {
+        if self.remaining == 0 {
+            return Err(DutyCycleError::Exhausted);
+        }
+
+        limiter.try_send(self.freq_hz, self.airtime_us, now_ms)?;
+        self.remaining -= 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FREQ: u32 = 868_300_000;
+
+    #[test]
+    fn test_budget() {
+        assert_eq!(EU868_G.budget_us(), 36_000_000);
+        assert_eq!(EU868_G2.budget_us(), 360_000_000);
+
+        let mut limiter = DutyCycleLimiter::new(EU_BANDS);
+        assert_eq!(limiter.band(869_500_000), Some(2));
+        assert_eq!(
+            limiter.try_send(868_650_000, 1000, 0),
+            Err(DutyCycleError::UnknownBand)
+        );
+        assert_eq!(
+            limiter.check(FREQ, 36_000_001, 0),
+            Err(DutyCycleError::TooLong)
+        );
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let mut limiter = DutyCycleLimiter::new(EU_BANDS);
+
+        // 20 s in the first minute and 16 s five minutes later
+        limiter.try_send(FREQ, 20_000_000, 1_000).unwrap();
+        limiter.try_send(FREQ, 16_000_000, 5 * BUCKET_MS).unwrap();
+        assert_eq!(limiter.available_us(FREQ, 10 * BUCKET_MS), Ok(0));
+
+        // The first transmission leaves the window one hour after the
+        // end of its bucket
+        assert_eq!(
+            limiter.check(FREQ, 1_000_000, 10 * BUCKET_MS),
+            Err(DutyCycleError::Wait {
+                until_ms: WINDOW_MS + BUCKET_MS
+            })
+        );
+        // Both have to leave for a long one
+        assert_eq!(
+            limiter.check(FREQ, 30_000_000, 10 * BUCKET_MS),
+            Err(DutyCycleError::Wait {
+                until_ms: WINDOW_MS + 6 * BUCKET_MS
+            })
+        );
+
+        assert!(limiter.try_send(FREQ, 1_000_000, WINDOW_MS).is_err());
+        assert!(limiter
+            .try_send(FREQ, 1_000_000, 1_000 + WINDOW_MS)
+            .is_err());
+        assert!(limiter
+            .try_send(FREQ, 1_000_000, WINDOW_MS + BUCKET_MS)
+            .is_ok());
+        assert_eq!(limiter.used_us(FREQ, 3 * WINDOW_MS), Ok(0));
+    }
+
+    #[test]
+    fn test_end_of_bucket() {
+        let mut limiter = DutyCycleLimiter::new(EU_BANDS);
+        let sent_ms = BUCKET_MS - 1;
+        limiter.try_send(FREQ, 36_000_000, sent_ms).unwrap();
+
+        assert_eq!(
+            limiter.check(FREQ, 1_000, sent_ms + WINDOW_MS - 1),
+            Err(DutyCycleError::Wait {
+                until_ms: sent_ms + 1 + WINDOW_MS
+            })
+        );
+        assert_eq!(limiter.used_us(FREQ, sent_ms + 1 + WINDOW_MS), Ok(0));
+    }
+
+    #[test]
+    fn test_retries() {
+        let mut limiter = DutyCycleLimiter::new(EU_BANDS);
+        limiter.record(FREQ, 30_000_000, 0);
+
+        // Three attempts don't fit, two do
+        assert!(limiter.retries(FREQ, 2_500_000, 3, 0).is_err());
+        let mut retries = limiter.retries(FREQ, 2_500_000, 2, 0).unwrap();
+        retries.attempt(&mut limiter, 0).unwrap();
+        retries.attempt(&mut limiter, 1_000).unwrap();
+        assert_eq!(retries.remaining(), 0);
+        assert_eq!(
+            retries.attempt(&mut limiter, 2_000),
+            Err(DutyCycleError::Exhausted)
+        );
+        assert_eq!(limiter.used_us(FREQ, 2_000), Ok(35_000_000));
+
+        // The total airtime saturates
+        assert_eq!(
+            limiter.retries(FREQ, u64::MAX / 2, 3, 2_000),
+            Err(DutyCycleError::TooLong)
+        );
+    }
+}
@@ -0,0 +1,330 @@
+// Radio framing for transmitters without a packet engine.
+//
+// Packet radios add the preamble and sync word in hardware. Simple
+// transmitters need both to be part of the transmitted data, receivers
+// without a packet engine look for the sync word with FrameSync.
+//
+// The framing also selects the line code of the link, 6b/8b unless
+// changed with Framing::with_line_code, the layout of the codewords in
+// a packet and the cross-packet interleaver depth, see interleave.
+
+use heapless::Vec;
+use ignore_result::Ignore as _;
+
+use crate::interleave::{PacketInterleave, MAX_INTERLEAVE_DEPTH};
+use crate::line::{LineCode, LineCoded, LineCoding};
+use crate::packet::{
+    GolayDecoderResult, PacketData, PacketWithGolay, PacketWithInterleave, PacketWithoutDC,
+    MAX_RADIO_PACKET_SIZE,
+};
+
+// Maximum size of a framed packet
+pub const MAX_FRAME_SIZE: usize = 64;
+
+// Radio packet with the framing applied
+pub type RadioFrame = Vec<u8, MAX_FRAME_SIZE>;
+
+// Preamble and sync word sent in front of every packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Framing<'s> {
+    // Number of 0xAA preamble bytes
+    pub preamble: usize,
+    pub sync_word: &'s [u8],
+    pub line_code: LineCoding,
+    pub packet_interleave: PacketInterleave,
+    // Consecutive packets interleaved with each other, 1 for none,
+    // set with with_interleave_depth
+    interleave_depth: usize,
+}
+
+impl<'s> Framing<'s> {
+    pub const PREAMBLE_BYTE: u8 = 0xaa;
+
+    // Packet radio handles the framing
+    pub const NONE: Framing<'static> = Framing {
+        preamble: 0,
+        sync_word: &[],
+        line_code: LineCoding::Dc6b8b,
+        packet_interleave: PacketInterleave::Spread,
+        interleave_depth: 1,
+    };
+
+    // The framed packet must fit MAX_FRAME_SIZE bytes
+    pub const fn new(preamble: usize, sync_word: &'s [u8]) -> Self {
+        Self {
+            preamble,
+            sync_word,
+            line_code: LineCoding::Dc6b8b,
+            packet_interleave: PacketInterleave::Spread,
+            interleave_depth: 1,
+        }
+        .checked()
+    }
+
+    pub const fn with_line_code(self, line_code: LineCoding) -> Self {
+        Self { line_code, ..self }.checked()
+    }
+
+    pub const fn with_packet_interleave(self, packet_interleave: PacketInterleave) -> Self {
+        Self {
+            packet_interleave,
+            ..self
+        }
+    }
+
+    pub const fn with_interleave_depth(self, interleave_depth: usize) -> Self {
+        assert!(interleave_depth >= 1 && interleave_depth <= MAX_INTERLEAVE_DEPTH);
+        Self {
+            interleave_depth,
+            ..self
+        }
+    }
+
+    pub const fn interleave_depth(&self) -> usize {
+        self.interleave_depth
+    }
+
+    // Number of bytes sent on air per packet
+    pub const fn frame_size(&self) -> usize {
+        self.preamble + self.sync_word.len() + self.line_code.packet_size()
+    }
+
+    const fn checked(self) -> Self {
+        assert!(
+            self.frame_size() <= MAX_FRAME_SIZE,
+            "Framing larger than MAX_FRAME_SIZE"
+        );
+        self
+    }
+
+    fn framed(&self, data: &[u8]) -> RadioFrame {
+        debug_assert!(self.frame_size() <= MAX_FRAME_SIZE);
+
+        let mut frame = RadioFrame::new();
+        for _ in 0..self.preamble {
+            frame.push(Self::PREAMBLE_BYTE).ignore();
+        }
+        frame.extend_from_slice(self.sync_word).ignore();
+        frame.extend_from_slice(data).ignore();
+        frame
+    }
+
+    // Prepend the preamble and sync word to an already 6b/8b coded packet
+    pub fn frame(&self, p: &PacketWithoutDC) -> RadioFrame {
+        self.framed(&p.data())
+    }
+
+    // Encode with the packet interleaver and line code of the framing
+    // and frame the result, without cross-packet interleaving
+    pub fn frame_packet(&self, p: &PacketData) -> RadioFrame {
+        self.framed(&self.encode_packet(p))
+    }
+
+    pub fn frame_interleaved(&self, p: &PacketWithInterleave) -> RadioFrame {
+        self.framed(&self.line_code.encode(p))
+    }
+}
+
+// Packets coded with the packet interleaver and line code of the
+// framing, without preamble and sync word
+impl LineCode for Framing<'_> {
+    fn size(&self) -> usize {
+        self.line_code.size()
+    }
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded {
+        self.line_code.encode(p)
+    }
+
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave {
+        self.line_code.decode(data)
+    }
+
+    fn encode_packet(&self, p: &PacketData) -> LineCoded {
+        let p = PacketWithGolay::from(p);
+        self.encode(&self.packet_interleave.interleave(&p))
+    }
+
+    fn decode_packet(&self, data: &[u8]) -> GolayDecoderResult {
+        let p = self.decode(data);
+        GolayDecoderResult::from(&self.packet_interleave.deinterleave(&p))
+    }
+}
+
+// Finds the sync word in a demodulated bit stream and collects the radio
+// packet following it, as many bytes as the line code sends. Bits are
+// pushed in the order they were received, bytes are sent MSb first.
+#[derive(Clone, Debug)]
+pub struct FrameSync {
+    sync: u64,
+    mask: u64,
+    sync_bits: usize,
+    max_errors: u32,
+
+    // Last received bits while looking for the sync word
+    shift: u64,
+    seen: usize,
+
+    // Packet being collected after the sync word
+    collecting: bool,
+    bits: usize,
+    size: usize,
+    packet: [u8; MAX_RADIO_PACKET_SIZE],
+}
+
+impl FrameSync {
+    // The sync word is at most 8 bytes, up to max_errors bits of it
+    // may be wrong
+    pub fn new(sync_word: &[u8], max_errors: u32) -> Self {
+        debug_assert!(!sync_word.is_empty() && sync_word.len() <= 8);
+
+        let sync_bits = sync_word.len() * 8;
+        Self {
+            sync: sync_word
+                .iter()
+                .fold(0_u64, |acc, b| (acc << 8) | *b as u64),
+            mask: u64::MAX >> (64 - sync_bits),
+            sync_bits,
+            max_errors,
+            shift: 0,
+            seen: 0,
+            collecting: false,
+            bits: 0,
+            size: LineCoding::Dc6b8b.packet_size(),
+            packet: [0; MAX_RADIO_PACKET_SIZE],
+        }
+    }
+
+    // Collect packets of the given line code, 6b/8b by default
+    pub fn with_line_code(self, line_code: LineCoding) -> Self {
+        Self {
+            size: line_code.packet_size(),
+            ..self
+        }
+    }
+
+    // Sync word and line code of the framing
+    pub fn for_framing(framing: &Framing, max_errors: u32) -> Self {
+        Self::new(framing.sync_word, max_errors).with_line_code(framing.line_code)
+    }
+
+    // Back to looking for the sync word
+    pub fn reset(&mut self) {
+        self.shift = 0;
+        self.seen = 0;
+        self.collecting = false;
+    }
+
+    pub fn collecting(&self) -> bool {
+        self.collecting
+    }
+
+    // Returns the packet once its last bit arrived
+    pub fn push(&mut self, bit: bool) -> Option<LineCoded> {
+        if self.collecting {
+            let byte = &mut self.packet[self.bits / 8];
+            *byte = (*byte << 1) | bit as u8;
+            self.bits += 1;
+            if self.bits < self.size * 8 {
+                return None;
+            }
+            self.reset();
+            // Cannot fail, the size is at most MAX_RADIO_PACKET_SIZE
+            return Some(LineCoded::from_slice(&self.packet[..self.size]).unwrap());
+        }
+
+        self.shift = (self.shift << 1) | bit as u64;
+        self.seen += 1;
+        if self.seen >= self.sync_bits
+            && ((self.shift ^ self.sync) & self.mask).count_ones() <= self.max_errors
+        {
+            self.collecting = true;
+            self.bits = 0;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::RADIO_PACKET_SIZE;
+
+    #[test]
+    fn test_frame() {
+        let p = PacketWithoutDC::new(&[0x55; RADIO_PACKET_SIZE]);
+
+        let frame = Framing::NONE.frame(&p);
+        assert_eq!(frame.as_slice(), &p.data());
+
+        let framing = Framing::new(4, &[0x2d, 0xd4]);
+        let frame = framing.frame(&p);
+        assert_eq!(frame.len(), framing.frame_size());
+        assert_eq!(&frame[..6], &[0xaa, 0xaa, 0xaa, 0xaa, 0x2d, 0xd4]);
+        assert_eq!(&frame[6..], &p.data());
+
+        // 48 byte Manchester packets leave room for 16 bytes of framing
+        let framing = Framing::new(14, &[0x2d, 0xd4]).with_line_code(LineCoding::Manchester);
+        assert_eq!(framing.frame_size(), MAX_FRAME_SIZE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Framing larger than MAX_FRAME_SIZE")]
+    fn test_frame_too_large() {
+        Framing::new(15, &[0x2d, 0xd4]).with_line_code(LineCoding::Manchester);
+    }
+
+    fn push_bytes(sync: &mut FrameSync, data: &[u8]) -> Option<LineCoded> {
+        let mut found = None;
+        for b in data {
+            for bit in (0..8).rev() {
+                if let Some(p) = sync.push(b >> bit & 1 == 1) {
+                    found = Some(p);
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn test_frame_sync() {
+        let p = PacketWithoutDC::new(&[0x5a; RADIO_PACKET_SIZE]);
+        let framing = Framing::new(4, &[0x2d, 0xd4]);
+        let mut sync = FrameSync::new(framing.sync_word, 1);
+
+        // Noise in front, the packet starts at an odd bit offset
+        let mut bits: Vec<u8, 64> = Vec::from_slice(&[0x13, 0x37]).unwrap();
+        bits.extend_from_slice(&framing.frame(&p)).unwrap();
+        bits.push(0).unwrap();
+        let shifted: Vec<u8, 64> = bits.windows(2).map(|w| (w[0] << 3) | (w[1] >> 5)).collect();
+        assert_eq!(
+            push_bytes(&mut sync, &shifted),
+            Some(LineCoded::from_slice(&p.data()).unwrap())
+        );
+        assert!(!sync.collecting());
+
+        // One bit error in the sync word is fine, two are not
+        let mut frame = framing.frame(&p);
+        frame[4] ^= 0x01;
+        assert_eq!(
+            push_bytes(&mut sync, &frame),
+            Some(LineCoded::from_slice(&p.data()).unwrap())
+        );
+        frame[5] ^= 0x80;
+        sync.reset();
+        assert_eq!(push_bytes(&mut sync, &frame), None);
+    }
+
+    #[test]
+    fn test_frame_sync_line_code() {
+        let framing = Framing::new(4, &[0x2d, 0xd4]).with_line_code(LineCoding::Manchester);
+        let mut p = PacketData::new();
+        p.data.extend_from_slice(&[0x42; 11]).unwrap();
+        let frame = framing.frame_packet(&p);
+
+        let mut sync = FrameSync::for_framing(&framing, 0);
+        let found = push_bytes(&mut sync, &frame).expect("Packet not found");
+        assert_eq!(found.len(), 48);
+        assert_eq!(found, framing.line_code.encode_packet(&p));
+    }
+}
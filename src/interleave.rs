@@ -0,0 +1,192 @@
+// Packet and cross-packet interleaving.
+//
+// PacketInterleave selects how the eight Golay codewords of a packet are
+// laid out in its 24 bytes. Spread, the default, puts one bit of every
+// codeword into each byte, a burst of up to three bytes costs every
+// codeword three bits, which the Golay code corrects. Sequential sends
+// the codewords back to back, for links with scattered bit errors.
+// Neither survives a fade that wipes a whole frame. The layout is part
+// of the Framing, receivers take it from there, see for example
+// StreamDecoder::for_framing.
+//
+// With a depth above one, blocks of that many consecutive packets are
+// interleaved once more, byte by byte: byte i of frame f is byte i of
+// packet (i + f) % depth. A wiped frame then costs every packet of the
+// block 24 / depth bytes, with Spread 24 / depth bits per codeword. Only
+// depth 8 brings that down to the three bits Golay corrects, at depth 2
+// and 4 (12 and 6 bits) a wiped frame still loses the message. No byte
+// permutation helps there, the frame carries too many bits of the block.
+//
+// The last block of a message holds the remaining packets and uses their
+// number as depth, so the receiver deinterleaves whatever it collected
+// once the message is over. Unless the message fills its last block, a
+// wiped frame in it loses the message at any depth. Depth 1 is no cross-
+// packet interleaving.
+
+use ufmt::derive::uDebug;
+
+use crate::packet::{PacketWithGolay, PacketWithInterleave};
+
+pub const MAX_INTERLEAVE_DEPTH: usize = 8;
+
+// Layout of the Golay codewords in a packet
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PacketInterleave {
+    // Bit N of every codeword in byte N, codeword 0 in the MSb
+    #[default]
+    Spread,
+    // Codewords back to back, three bytes each
+    Sequential,
+}
+
+impl PacketInterleave {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "spread" => Some(Self::Spread),
+            "sequential" => Some(Self::Sequential),
+            _ => None,
+        }
+    }
+
+    pub fn interleave(&self, p: &PacketWithGolay) -> PacketWithInterleave {
+        match self {
+            Self::Spread => PacketWithInterleave::from(p),
+            Self::Sequential => PacketWithInterleave::from(p.data()),
+        }
+    }
+
+    pub fn deinterleave(&self, p: &PacketWithInterleave) -> PacketWithGolay {
+        match self {
+            Self::Spread => PacketWithGolay::from(p),
+            Self::Sequential => PacketWithGolay::from(p.data()),
+        }
+    }
+}
+
+// Packets to frames, the depth is the length of the block. Blocks are
+// at most MAX_INTERLEAVE_DEPTH packets long.
+pub fn interleave(block: &mut [PacketWithInterleave]) {
+    let n = block.len();
+    assert!(n <= MAX_INTERLEAVE_DEPTH, "Interleaver block too long");
+    if n < 2 {
+        return;
+    }
+
+    let mut src = [[0_u8; 24]; MAX_INTERLEAVE_DEPTH];
+    for (s, p) in src.iter_mut().zip(block.iter()) {
+        *s = p.data();
+    }
+    for (f, p) in block.iter_mut().enumerate() {
+        *p = PacketWithInterleave::from(core::array::from_fn(|i| src[(i + f) % n][i]));
+    }
+}
+
+// Frames to packets, reverses interleave
+pub fn deinterleave(block: &mut [PacketWithInterleave]) {
+    let n = block.len();
+    assert!(n <= MAX_INTERLEAVE_DEPTH, "Interleaver block too long");
+    if n < 2 {
+        return;
+    }
+
+    let mut src = [[0_u8; 24]; MAX_INTERLEAVE_DEPTH];
+    for (s, p) in src.iter_mut().zip(block.iter()) {
+        *s = p.data();
+    }
+    for (k, p) in block.iter_mut().enumerate() {
+        *p = PacketWithInterleave::from(core::array::from_fn(|i| src[(k + n - i % n) % n][i]));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::packet::{GolayDecoderResult, PacketData};
+
+    fn test_block(n: usize) -> Vec<PacketData, MAX_INTERLEAVE_DEPTH> {
+        (0..n)
+            .map(|k| {
+                let mut p = PacketData::new();
+                p.data = (0..11).map(|i| (k * 16 + i * 7) as u8).collect();
+                p
+            })
+            .collect()
+    }
+
+    fn encode(packets: &[PacketData]) -> Vec<PacketWithInterleave, MAX_INTERLEAVE_DEPTH> {
+        packets
+            .iter()
+            .map(|p| PacketWithInterleave::from(&PacketWithGolay::from(p)))
+            .collect()
+    }
+
+    fn decode(p: &PacketWithInterleave) -> GolayDecoderResult {
+        GolayDecoderResult::from(&PacketWithGolay::from(p))
+    }
+
+    #[test]
+    fn test_symmetric() {
+        for n in 1..=MAX_INTERLEAVE_DEPTH {
+            let packets = encode(&test_block(n));
+            let mut block = packets.clone();
+            interleave(&mut block);
+            if n > 1 {
+                assert_ne!(block, packets);
+            }
+            deinterleave(&mut block);
+            assert_eq!(block, packets, "depth {n}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Interleaver block too long")]
+    fn test_block_too_long() {
+        interleave(&mut [PacketWithInterleave::default(); MAX_INTERLEAVE_DEPTH + 1]);
+    }
+
+    #[test]
+    fn test_wiped_frame() {
+        for n in [2, 4, MAX_INTERLEAVE_DEPTH] {
+            let data = test_block(n);
+            let mut block = encode(&data);
+            interleave(&mut block);
+            block[1] = PacketWithInterleave::from([0xff; 24]);
+            deinterleave(&mut block);
+
+            // 24 / n bits per codeword, too many below depth 8
+            let ok = block
+                .iter()
+                .zip(data.iter())
+                .all(|(p, d)| decode(p).data.data == d.data);
+            assert_eq!(ok, n == MAX_INTERLEAVE_DEPTH, "depth {n}");
+        }
+    }
+
+    #[test]
+    fn test_packet_interleave() {
+        let data = &test_block(1)[0];
+        let golay = PacketWithGolay::from(data);
+        for layout in [PacketInterleave::Spread, PacketInterleave::Sequential] {
+            let p = layout.interleave(&golay);
+            assert_eq!(layout.deinterleave(&p), golay);
+        }
+        assert_eq!(
+            PacketInterleave::Sequential.interleave(&golay).data(),
+            golay.data()
+        );
+
+        // A three byte burst, Spread corrects it, Sequential loses a codeword
+        for (layout, ok) in [
+            (PacketInterleave::Spread, true),
+            (PacketInterleave::Sequential, false),
+        ] {
+            let mut bytes = layout.interleave(&golay).data();
+            bytes[3..6].iter_mut().for_each(|b| *b ^= 0xff);
+            let dec =
+                GolayDecoderResult::from(&layout.deinterleave(&PacketWithInterleave::from(bytes)));
+            assert_eq!(dec.data.data == data.data, ok, "{layout:?}");
+        }
+    }
+}
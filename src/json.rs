@@ -0,0 +1,315 @@
+// rtl_433 style JSON output of received messages, one object per message:
+//
+//   {"model":"LASO-V2","id":4660,"packet_type":16,"data":"0102",
+//    "listens":0,"rssi":64,"lna":2,"errors":1,"confidence":"Corrected","mic":"PASS"}
+//
+// Payloads of known packet types are turned into typed fields by
+// a PayloadCodec, the others are written as hex into "data".
+//
+// A message that failed to decode is written without payload, with
+// "mic":"FAIL" and the reason in "error":
+//
+//   {"model":"LASO-V2","id":4660,"packet_type":16,"rssi":64,"lna":2,
+//    "errors":9,"mic":"FAIL","error":"CrcFailed"}
+
+use core::fmt::{Display, Write as _};
+use std::{format, string::String};
+
+use crate::message::MessageVersion;
+use crate::rx::{RxErrorKind, RxMessage, RxMessageDecoder};
+
+// Adds the typed fields of a payload to the JSON object
+pub trait PayloadCodec {
+    // Returns false when the packet type is unknown, no fields
+    // may be written in that case
+    fn fields(&self, packet_type: Option<u32>, payload: &[u8], json: &mut JsonObject) -> bool;
+}
+
+// No typed fields, the payload is always written as hex
+pub struct RawPayload;
+
+impl PayloadCodec for RawPayload {
+    fn fields(&self, _: Option<u32>, _: &[u8], _: &mut JsonObject) -> bool {
+        false
+    }
+}
+
+impl<F: Fn(Option<u32>, &[u8], &mut JsonObject) -> bool> PayloadCodec for F {
+    fn fields(&self, packet_type: Option<u32>, payload: &[u8], json: &mut JsonObject) -> bool {
+        self(packet_type, payload, json)
+    }
+}
+
+// Values written by JsonObject::num
+pub trait JsonNumber: Display {
+    // JSON has no NaN or infinity
+    fn is_finite(&self) -> bool {
+        true
+    }
+}
+
+macro_rules! json_integer {
+    ($($t:ty),*) => {
+        $(impl JsonNumber for $t {})*
+    };
+}
+
+json_integer!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl JsonNumber for f32 {
+    fn is_finite(&self) -> bool {
+        f32::is_finite(*self)
+    }
+}
+
+impl JsonNumber for f64 {
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
+}
+
+// Single line JSON object writer
+pub struct JsonObject {
+    out: String,
+}
+
+impl Default for JsonObject {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonObject {
+    pub fn new() -> Self {
+        Self {
+            out: String::from("{"),
+        }
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.out.len() > 1 {
+            self.out.push(',');
+        }
+        self.string(key);
+        self.out.push(':');
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if (c as u32) < 0x20 => {
+                    let _ = write!(self.out, "\\u{:04x}", c as u32);
+                }
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    pub fn str(&mut self, key: &str, value: &str) -> &mut Self {
+        self.key(key);
+        self.string(value);
+        self
+    }
+
+    // NaN and infinity are written as null
+    pub fn num(&mut self, key: &str, value: impl JsonNumber) -> &mut Self {
+        self.key(key);
+        if value.is_finite() {
+            let _ = write!(self.out, "{value}");
+        } else {
+            self.out.push_str("null");
+        }
+        self
+    }
+
+    pub fn hex(&mut self, key: &str, data: &[u8]) -> &mut Self {
+        self.key(key);
+        self.out.push('"');
+        for b in data {
+            let _ = write!(self.out, "{b:02x}");
+        }
+        self.out.push('"');
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push('}');
+        self.out
+    }
+}
+
+pub fn model(version: MessageVersion) -> &'static str {
+    match version {
+        #[cfg(feature = "legacy")]
+        MessageVersion::LegacyLaso => "LASO-Legacy",
+        MessageVersion::V2 => "LASO-V2",
+        MessageVersion::V2Short => "LASO-V2Short",
+        MessageVersion::Naked => "LASO-Naked",
+        MessageVersion::NakedShort => "LASO-NakedShort",
+    }
+}
+
+// Whether the message carries an integrity check, naked messages have none
+fn has_mic(version: MessageVersion) -> bool {
+    match version {
+        #[cfg(feature = "legacy")]
+        MessageVersion::LegacyLaso => true,
+        MessageVersion::V2 | MessageVersion::V2Short => true,
+        MessageVersion::Naked | MessageVersion::NakedShort => false,
+    }
+}
+
+// Serialize the message as a single line JSON object. The time is
+// written first when given, rtl_433 uses "YYYY-MM-DD HH:MM:SS".
+pub fn to_json<const N: usize>(
+    rx: &RxMessage<N>,
+    time: Option<&str>,
+    codec: &impl PayloadCodec,
+) -> String {
+    let msg = &rx.msg;
+    let mut json = JsonObject::new();
+
+    if let Some(time) = time {
+        json.str("time", time);
+    }
+    json.str("model", model(msg.version))
+        .num("id", msg.source_address);
+    if let Some(t) = msg.packet_type {
+        json.num("packet_type", t);
+    }
+
+    // The codec writes into its own object, the fields of a codec
+    // that gives up halfway are dropped
+    let mut fields = JsonObject::new();
+    if codec.fields(msg.packet_type, &msg.data, &mut fields) {
+        let fields = fields.finish();
+        let inner = &fields[1..fields.len() - 1];
+        if !inner.is_empty() {
+            json.out.push(',');
+            json.out.push_str(inner);
+        }
+    } else {
+        json.hex("data", &msg.data);
+    }
+
+    json.num("listens", msg.will_listen as u8)
+        .num("rssi", rx.rssi)
+        .num("lna", rx.lna)
+        .num("errors", rx.errors)
+        .str("confidence", &format!("{:?}", rx.confidence));
+    if has_mic(msg.version) {
+        json.str("mic", "PASS");
+    }
+
+    json.finish()
+}
+
+// Serialize what is known about a message that failed to decode, the
+// header as far as it was received and the radio metadata
+pub fn failed_to_json<const N: usize>(
+    rx: &RxMessageDecoder<N>,
+    kind: RxErrorKind,
+    time: Option<&str>,
+) -> String {
+    let msg = &rx.msg;
+    let mut json = JsonObject::new();
+
+    if let Some(time) = time {
+        json.str("time", time);
+    }
+    json.str("model", model(msg.version))
+        .num("id", msg.source_address);
+    if let Some(t) = msg.packet_type {
+        json.num("packet_type", t);
+    }
+    json.num("rssi", rx.rssi)
+        .num("lna", rx.lna)
+        .num("errors", rx.errors)
+        .str("mic", "FAIL")
+        .str("error", &format!("{kind:?}"));
+
+    json.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rx::RxConfidence;
+
+    fn test_message() -> RxMessage<16> {
+        let mut rx = RxMessage::<16>::default();
+        rx.msg.source_address = 0x1234;
+        rx.msg.packet_type = Some(0x10);
+        rx.msg.data.extend_from_slice(&[0x01, 0x02]).unwrap();
+        rx.rssi = 64;
+        rx.lna = 2;
+        rx.errors = 1;
+        rx.confidence = RxConfidence::Corrected;
+        rx
+    }
+
+    #[test]
+    fn test_raw() {
+        assert_eq!(
+            to_json(&test_message(), Some("2024-05-01 12:00:00"), &RawPayload),
+            r#"{"time":"2024-05-01 12:00:00","model":"LASO-V2","id":4660,"packet_type":16,"data":"0102","listens":0,"rssi":64,"lna":2,"errors":1,"confidence":"Corrected","mic":"PASS"}"#
+        );
+
+        let mut naked = test_message();
+        naked.msg.version = MessageVersion::Naked;
+        naked.msg.packet_type = None;
+        assert_eq!(
+            to_json(&naked, None, &RawPayload),
+            r#"{"model":"LASO-Naked","id":4660,"data":"0102","listens":0,"rssi":64,"lna":2,"errors":1,"confidence":"Corrected"}"#
+        );
+    }
+
+    #[test]
+    fn test_codec() {
+        let codec = |packet_type: Option<u32>, payload: &[u8], json: &mut JsonObject| {
+            if packet_type != Some(0x10) || payload.len() < 2 {
+                return false;
+            }
+            let t = i16::from_be_bytes([payload[0], payload[1]]) as f32 / 10.0;
+            json.num("temperature_C", t).str("note", "a \"quoted\"\n");
+            true
+        };
+        assert_eq!(
+            to_json(&test_message(), None, &codec),
+            r#"{"model":"LASO-V2","id":4660,"packet_type":16,"temperature_C":25.8,"note":"a \"quoted\"\n","listens":0,"rssi":64,"lna":2,"errors":1,"confidence":"Corrected","mic":"PASS"}"#
+        );
+    }
+
+    #[test]
+    fn test_failed() {
+        let mut rx = RxMessageDecoder::<16>::default();
+        rx.msg.source_address = 0x1234;
+        rx.msg.packet_type = Some(0x10);
+        rx.rssi = 64;
+        rx.errors = 9;
+        assert_eq!(
+            failed_to_json(&rx, RxErrorKind::CrcFailed, None),
+            r#"{"model":"LASO-V2","id":4660,"packet_type":16,"rssi":64,"lna":0,"errors":9,"mic":"FAIL","error":"CrcFailed"}"#
+        );
+    }
+
+    #[test]
+    fn test_not_finite() {
+        let mut json = JsonObject::new();
+        json.num("nan", f32::NAN)
+            .num("inf", f64::INFINITY)
+            .num("neg", f32::NEG_INFINITY)
+            .num("x", -1.5_f64);
+        assert_eq!(
+            json.finish(),
+            r#"{"nan":null,"inf":null,"neg":null,"x":-1.5}"#
+        );
+    }
+}
@@ -0,0 +1,192 @@
+// Listen before talk with random backoff.
+//
+// Before the first packet of a message the channel is sampled a few
+// times, when any sample is above the threshold the channel is busy and
+// the scheduler waits a random number of slots. The backoff window doubles
+// with every busy attempt up to a limit. Follow-up packets of the message
+// are sent right after the first one, the receiver expects them back to
+// back.
+
+use ufmt::derive::uDebug;
+
+use crate::frame::Framing;
+use crate::tx::{Sender, TxMessage};
+
+// Radio operations needed for carrier sense and transmission
+pub trait Radio {
+    type Error;
+
+    // Current received signal strength in dBm
+    fn rssi(&mut self) -> Result<i16, Self::Error>;
+
+    // Send a single frame, blocks until it is on air
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    fn delay_us(&mut self, us: u32);
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LbtConfig {
+    // Channel is busy when any sample is above this level
+    pub threshold_dbm: i16,
+    // Number of RSSI samples per carrier sense
+    pub samples: u8,
+    pub sample_interval_us: u32,
+    // Length of a single backoff slot
+    pub slot_us: u32,
+    // The backoff window is 2^exp slots, starting at min and doubling
+    // after every busy attempt up to max
+    pub min_backoff_exp: u8,
+    pub max_backoff_exp: u8,
+    // Give up after this many busy carrier senses
+    pub max_attempts: u8,
+}
+
+impl Default for LbtConfig {
+    fn default() -> Self {
+        // ETSI EN 300 220 minimum listen time is 5 ms
+        Self {
+            threshold_dbm: -90,
+            samples: 5,
+            sample_interval_us: 1000,
+            slot_us: 1000,
+            min_backoff_exp: 2,
+            max_backoff_exp: 6,
+            max_attempts: 8,
+        }
+    }
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LbtError<E> {
+    // The channel stayed busy for all attempts
+    ChannelBusy,
+    Radio(E),
+}
+
+impl<E> From<E> for LbtError<E> {
+    fn from(e: E) -> Self {
+        LbtError::Radio(e)
+    }
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LbtStats {
+    // Carrier senses that found the channel busy
+    pub busy: u8,
+    // Total time spent in backoff
+    pub backoff_us: u32,
+    pub packets: usize,
+}
+
+// Small xorshift generator for the backoff, no need for anything better
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Backoff(u32);
+
+impl Backoff {
+    fn new(seed: u32) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(if seed == 0 { 0x9e37_79b9 } else { seed })
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+pub struct LbtScheduler<R: Radio> {
+    pub radio: R,
+    pub config: LbtConfig,
+    rng: Backoff,
+}
+
+impl<R: Radio> LbtScheduler<R> {
+    // Nodes sharing a channel should use different seeds,
+    // the node address is a good choice
+    pub fn new(radio: R, config: LbtConfig, seed: u32) -> Self {
+        Self {
+            radio,
+            config,
+            rng: Backoff::new(seed),
+        }
+    }
+
+    pub fn release(self) -> R {
+        self.radio
+    }
+
+    // Sample the channel, returns true when no sample is above the threshold
+    pub fn channel_clear(&mut self) -> Result<bool, R::Error> {
+        for n in 0..self.config.samples {
+            if n > 0 {
+                self.radio.delay_us(self.config.sample_interval_us);
+            }
+            if self.radio.rssi()? > self.config.threshold_dbm {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Wait until the channel is clear, returns the statistics of the wait
+    pub fn wait_clear(&mut self) -> Result<LbtStats, LbtError<R::Error>> {
+        let mut stats = LbtStats::default();
+        let mut exp = self.config.min_backoff_exp;
+
+        while !self.channel_clear()? {
+            stats.busy += 1;
+            if stats.busy >= self.config.max_attempts {
+                return Err(LbtError::ChannelBusy);
+            }
+
+            let slots = self.rng.next() % (1 << exp.min(31));
+            let wait = slots.saturating_mul(self.config.slot_us);
+            self.radio.delay_us(wait);
+            stats.backoff_us = stats.backoff_us.saturating_add(wait);
+            exp = exp.saturating_add(1).min(self.config.max_backoff_exp);
+        }
+
+        Ok(stats)
+    }
+
+    // Send all packets of the message once the channel is clear
+    pub fn send<M: TxMessage>(
+        &mut self,
+        sender: Sender<'_, M>,
+        framing: Framing<'_>,
+    ) -> Result<LbtStats, LbtError<R::Error>> {
+        let mut frames = sender.frames(framing).peekable();
+        if frames.peek().is_none() {
+            return Ok(LbtStats::default());
+        }
+
+        let mut stats = self.wait_clear()?;
+        for frame in frames {
+            self.radio.transmit(&frame)?;
+            stats.packets += 1;
+        }
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff_spread() {
+        let mut a = Backoff::new(1);
+        let mut b = Backoff::new(2);
+        let same = (0..64).filter(|_| a.next() % 16 == b.next() % 16).count();
+        assert!(same < 16);
+
+        let mut zero = Backoff::new(0);
+        assert_ne!(zero.next(), 0);
+    }
+}
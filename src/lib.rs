@@ -1,10 +1,32 @@
 #![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
 pub mod behavior;
+pub mod capture;
+pub mod combine;
 pub mod dc;
+pub mod duty;
+pub mod frame;
+pub mod interleave;
+#[cfg(feature = "std")]
+pub mod json;
 pub mod laso;
+pub mod lbt;
+pub mod line;
+pub mod link;
 pub mod message;
+#[cfg(feature = "std")]
+pub mod modem;
 pub mod packet;
+pub mod pcap;
+pub mod plan;
+pub mod pulse;
 pub mod raw;
 pub mod rx;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod stream;
 pub mod tx;
 pub mod util;
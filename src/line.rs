@@ -0,0 +1,324 @@
+// Line coding between the interleaved packet and the radio.
+//
+// The 24 interleaved bytes are turned into what the radio sends. LASO
+// uses the 6b/8b DC balancing code, radios with a different modem may
+// need something else:
+//
+//   Dc6b8b      32 bytes, the LASO default, see dc
+//   Manchester  48 bytes, every bit sent as two halves for clock recovery
+//   Pn9         24 bytes, XOR with the PN9 sequence, for radios that
+//               expect whitened data
+//   Identity    24 bytes, for radios that do their own line coding
+//
+// Both ends of a link have to use the same line code, the choice is
+// part of the Framing.
+
+use heapless::Vec;
+use ignore_result::Ignore as _;
+use ufmt::derive::uDebug;
+
+use crate::packet::{
+    GolayDecoderResult, PacketData, PacketWithGolay, PacketWithInterleave, PacketWithoutDC,
+    MAX_RADIO_PACKET_SIZE, RADIO_PACKET_SIZE,
+};
+
+const INTERLEAVED_SIZE: usize = 24;
+
+// Radio packet after line coding
+pub type LineCoded = Vec<u8, MAX_RADIO_PACKET_SIZE>;
+
+pub trait LineCode {
+    // Radio bytes per packet
+    fn size(&self) -> usize;
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded;
+
+    // Bytes missing at the end of a short packet decode as zeros,
+    // extra bytes are ignored
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave;
+
+    fn encode_packet(&self, p: &PacketData) -> LineCoded {
+        let p = PacketWithGolay::from(p);
+        self.encode(&PacketWithInterleave::from(&p))
+    }
+
+    // Decode received radio data
+    fn decode_packet(&self, data: &[u8]) -> GolayDecoderResult {
+        let p = self.decode(data);
+        GolayDecoderResult::from(&PacketWithGolay::from(&p))
+    }
+}
+
+// 6b/8b DC balancing, same as PacketWithoutDC
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Dc6b8b;
+
+impl LineCode for Dc6b8b {
+    fn size(&self) -> usize {
+        RADIO_PACKET_SIZE
+    }
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded {
+        // Cannot fail, MAX_RADIO_PACKET_SIZE is larger
+        Vec::from_slice(&PacketWithoutDC::from(p).data()).unwrap()
+    }
+
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave {
+        PacketWithInterleave::from(&PacketWithoutDC::new(data))
+    }
+}
+
+// G.E. Thomas Manchester code, a one is sent as 10, a zero as 01,
+// MSb first. Invalid pairs (00, 11) decode as their first half, the
+// Golay code has to correct them.
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Manchester;
+
+impl Manchester {
+    const fn expand(nibble: u8) -> u8 {
+        let mut out = 0;
+        let mut i = 0;
+        while i < 4 {
+            let one = (nibble >> (3 - i)) & 1;
+            out |= (0b01 << one) << (6 - 2 * i);
+            i += 1;
+        }
+        out
+    }
+
+    // First halves of four pairs
+    pub(crate) const fn compress(b: u8) -> u8 {
+        (b >> 4 & 0x8) | (b >> 3 & 0x4) | (b >> 2 & 0x2) | (b >> 1 & 0x1)
+    }
+}
+
+impl LineCode for Manchester {
+    fn size(&self) -> usize {
+        2 * INTERLEAVED_SIZE
+    }
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded {
+        let mut out = LineCoded::new();
+        for b in p.data() {
+            out.push(Self::expand(b >> 4)).ignore();
+            out.push(Self::expand(b & 0xf)).ignore();
+        }
+        out
+    }
+
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave {
+        let mut out = [0; INTERLEAVED_SIZE];
+        for (o, pair) in out.iter_mut().zip(data.chunks(2)) {
+            let hi = Self::compress(pair[0]);
+            let lo = pair.get(1).map_or(0, |b| Self::compress(*b));
+            *o = hi << 4 | lo;
+        }
+        PacketWithInterleave::from(out)
+    }
+}
+
+// Data whitening with the PN9 sequence (x^9 + x^5 + 1, all ones seed),
+// the same sequence CC1101 and SX127x radios use. Whitening is its own
+// inverse.
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pn9;
+
+impl Pn9 {
+    const fn sequence() -> [u8; INTERLEAVED_SIZE] {
+        let mut out = [0; INTERLEAVED_SIZE];
+        let mut state: u16 = 0x1ff;
+        let mut i = 0;
+        while i < INTERLEAVED_SIZE {
+            out[i] = state as u8;
+            let mut n = 0;
+            while n < 8 {
+                let bit = (state ^ state >> 5) & 1;
+                state = state >> 1 | bit << 8;
+                n += 1;
+            }
+            i += 1;
+        }
+        out
+    }
+
+    pub(crate) const SEQUENCE: [u8; INTERLEAVED_SIZE] = Self::sequence();
+}
+
+impl LineCode for Pn9 {
+    fn size(&self) -> usize {
+        INTERLEAVED_SIZE
+    }
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded {
+        p.data()
+            .iter()
+            .zip(Self::SEQUENCE)
+            .map(|(b, w)| b ^ w)
+            .collect()
+    }
+
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave {
+        let mut out = [0; INTERLEAVED_SIZE];
+        for (o, (b, w)) in out.iter_mut().zip(data.iter().zip(Self::SEQUENCE)) {
+            *o = b ^ w;
+        }
+        PacketWithInterleave::from(out)
+    }
+}
+
+// The interleaved packet as is
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Identity;
+
+impl LineCode for Identity {
+    fn size(&self) -> usize {
+        INTERLEAVED_SIZE
+    }
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded {
+        // Cannot fail, MAX_RADIO_PACKET_SIZE is larger
+        Vec::from_slice(&p.data()).unwrap()
+    }
+
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave {
+        let mut out = [0; INTERLEAVED_SIZE];
+        for (o, b) in out.iter_mut().zip(data) {
+            *o = *b;
+        }
+        PacketWithInterleave::from(out)
+    }
+}
+
+// Line code selected by the link configuration
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineCoding {
+    #[default]
+    Dc6b8b,
+    Manchester,
+    Pn9,
+    Identity,
+}
+
+impl LineCoding {
+    // Same as LineCode::size, usable in const context
+    pub const fn packet_size(&self) -> usize {
+        match self {
+            LineCoding::Dc6b8b => RADIO_PACKET_SIZE,
+            LineCoding::Manchester => 2 * INTERLEAVED_SIZE,
+            LineCoding::Pn9 | LineCoding::Identity => INTERLEAVED_SIZE,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "6b8b" => Some(LineCoding::Dc6b8b),
+            "manchester" => Some(LineCoding::Manchester),
+            "pn9" => Some(LineCoding::Pn9),
+            "none" => Some(LineCoding::Identity),
+            _ => None,
+        }
+    }
+}
+
+impl LineCode for LineCoding {
+    fn size(&self) -> usize {
+        self.packet_size()
+    }
+
+    fn encode(&self, p: &PacketWithInterleave) -> LineCoded {
+        match self {
+            LineCoding::Dc6b8b => Dc6b8b.encode(p),
+            LineCoding::Manchester => Manchester.encode(p),
+            LineCoding::Pn9 => Pn9.encode(p),
+            LineCoding::Identity => Identity.encode(p),
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> PacketWithInterleave {
+        match self {
+            LineCoding::Dc6b8b => Dc6b8b.decode(data),
+            LineCoding::Manchester => Manchester.decode(data),
+            LineCoding::Pn9 => Pn9.decode(data),
+            LineCoding::Identity => Identity.decode(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::{test_v2_packet, TEST_DATA};
+
+    const ALL: [LineCoding; 4] = [
+        LineCoding::Dc6b8b,
+        LineCoding::Manchester,
+        LineCoding::Pn9,
+        LineCoding::Identity,
+    ];
+
+    fn test_packet() -> PacketData {
+        test_v2_packet(&TEST_DATA)
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let p = PacketWithInterleave::from(core::array::from_fn(|i| (i * 37 + 5) as u8));
+        for code in ALL {
+            let enc = code.encode(&p);
+            assert_eq!(enc.len(), code.size(), "{code:?}");
+            assert_eq!(code.decode(&enc), p, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let p = test_packet();
+        for code in ALL {
+            let enc = code.encode_packet(&p);
+            let dec = code.decode_packet(&enc);
+            assert_eq!(dec.errors, 0, "{code:?}");
+            assert_eq!(dec.data.data, p.data, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn test_dc6b8b_matches_packet() {
+        let p = test_packet();
+        assert_eq!(
+            Dc6b8b.encode_packet(&p).as_slice(),
+            &p.encode_for_transmit().data()
+        );
+    }
+
+    #[test]
+    fn test_manchester() {
+        let p = PacketWithInterleave::from([0b1001_1100; 24]);
+        let enc = Manchester.encode(&p);
+        assert_eq!(&enc[..2], &[0b1001_0110, 0b1010_0101]);
+
+        // A broken pair decodes as its first half
+        let mut bad = enc;
+        bad[0] = 0b1101_0110;
+        assert_eq!(Manchester.decode(&bad), p);
+    }
+
+    #[test]
+    fn test_pn9_sequence() {
+        assert_eq!(
+            &Pn9::SEQUENCE[..8],
+            &[0xff, 0xe1, 0x1d, 0x9a, 0xed, 0x85, 0x33, 0x24]
+        );
+    }
+
+    #[test]
+    fn test_bit_errors() {
+        let p = test_packet();
+        for code in ALL {
+            let mut enc = code.encode_packet(&p);
+            enc[3] ^= 0x10;
+            enc[17] ^= 0x01;
+            let dec = code.decode_packet(&enc);
+            assert_eq!(dec.data.data, p.data, "{code:?}");
+        }
+    }
+}
@@ -0,0 +1,262 @@
+// Async glue between the packet encoder/decoder and the radio driver.
+//
+// The radio driver implements RadioTx and/or RadioRx, the functions below
+// take care of splitting a message into frames, the gaps between them and
+// collecting the packets of a received message.
+//
+// The traits use async fn directly. The futures are not Send, which is
+// fine for the single core executors this crate targets.
+
+use heapless::Vec;
+use ignore_result::Ignore as _;
+use ufmt::derive::uDebug;
+
+use crate::duty::{DutyCycleError, DutyCycleLimiter, Retries};
+use crate::frame::Framing;
+use crate::interleave::{deinterleave, MAX_INTERLEAVE_DEPTH};
+use crate::line::LineCode as _;
+use crate::packet::{GolayDecoderResult, PacketWithInterleave, MAX_RADIO_PACKET_SIZE};
+use crate::raw::RawReceiveData;
+use crate::rx::{RxDecodeError, RxMessage, RxMessageDecoder, RxState};
+use crate::tx::{Sender, TxMessage};
+
+// A single received radio packet, without the preamble and sync word.
+// Sized for the largest line code.
+pub type RxPacket = RawReceiveData<MAX_RADIO_PACKET_SIZE>;
+
+#[allow(async_fn_in_trait)]
+pub trait RadioTx {
+    type Error;
+
+    // Send a single frame, resolves once it is on air
+    async fn transmit(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    async fn delay_us(&mut self, us: u32);
+}
+
+#[allow(async_fn_in_trait)]
+pub trait RadioRx {
+    type Error;
+
+    // Wait for a single packet, returns false when nothing
+    // was received within the timeout
+    async fn receive(
+        &mut self,
+        packet: &mut RxPacket,
+        timeout_us: u32,
+    ) -> Result<bool, Self::Error>;
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkTiming {
+    // Gap between the packets of a message
+    pub inter_packet_us: u32,
+    // How long the receiver waits for the next packet of a message
+    pub packet_timeout_us: u32,
+    // How long a node listens after sending a message with the listens flag
+    pub listen_window_us: u32,
+}
+
+impl Default for LinkTiming {
+    fn default() -> Self {
+        Self {
+            inter_packet_us: 1_000,
+            packet_timeout_us: 100_000,
+            listen_window_us: 500_000,
+        }
+    }
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkError<E> {
+    Radio(E),
+    Decode(RxDecodeError),
+    // Nothing was sent, the attempt would break the duty cycle
+    DutyCycle(DutyCycleError),
+}
+
+impl<E> From<RxDecodeError> for LinkError<E> {
+    fn from(e: RxDecodeError) -> Self {
+        LinkError::Decode(e)
+    }
+}
+
+// Send all packets of the message, returns the number of packets sent
+pub async fn send_message<R: RadioTx, M: TxMessage>(
+    radio: &mut R,
+    sender: Sender<'_, M>,
+    framing: Framing<'_>,
+    timing: &LinkTiming,
+) -> Result<usize, R::Error> {
+    let mut sent = 0;
+    for frame in sender.frames(framing) {
+        if sent > 0 {
+            radio.delay_us(timing.inter_packet_us).await;
+        }
+        radio.transmit(&frame).await?;
+        sent += 1;
+    }
+
+    Ok(sent)
+}
+
+// Send the message as the next attempt of retries. The attempt is
+// recorded in the limiter before the first frame goes on air, nothing is
+// sent when the budget or the attempts are used up.
+pub async fn send_message_attempt<R: RadioTx, M: TxMessage, const B: usize>(
+    radio: &mut R,
+    sender: Sender<'_, M>,
+    framing: Framing<'_>,
+    timing: &LinkTiming,
+    limiter: &mut DutyCycleLimiter<B>,
+    retries: &mut Retries,
+    now_ms: u64,
+) -> Result<usize, LinkError<R::Error>> {
+    retries
+        .attempt(limiter, now_ms)
+        .map_err(LinkError::DutyCycle)?;
+    send_message(radio, sender, framing, timing)
+        .await
+        .map_err(LinkError::Radio)
+}
+
+// Receive a single message. Waits up to timeout_us for the first packet
+// and then LinkTiming::packet_timeout_us for every following one.
+// Returns None when no packet arrived at all.
+pub async fn receive_message<R: RadioRx, const N: usize>(
+    radio: &mut R,
+    timing: &LinkTiming,
+    timeout_us: u32,
+) -> Result<Option<RxMessage<N>>, LinkError<R::Error>> {
+    receive_message_framed(radio, &Framing::NONE, timing, timeout_us).await
+}
+
+// Same as receive_message, for links with a different line code or
+// cross-packet interleaving. Interleaved blocks are decoded once complete,
+// the last block of a message once no more packets come. A frame missed
+// completely shifts the rest of its block, only frames received with
+// errors are spread over the block.
+pub async fn receive_message_framed<R: RadioRx, const N: usize>(
+    radio: &mut R,
+    framing: &Framing<'_>,
+    timing: &LinkTiming,
+    timeout_us: u32,
+) -> Result<Option<RxMessage<N>>, LinkError<R::Error>> {
+    let mut rx: RxMessageDecoder<N> = RxMessageDecoder::default();
+    let mut packet = RxPacket::init();
+    let mut timeout = timeout_us;
+    let mut block = Block::default();
+
+    loop {
+        packet.clear();
+        let received = radio
+            .receive(&mut packet, timeout)
+            .await
+            .map_err(LinkError::Radio)?;
+
+        if !received {
+            block.flush(&mut rx, framing)?;
+            // Multi packet V2 messages are only over when no more packets come
+            return match rx.state() {
+                RxState::Idle => Ok(None),
+                _ => match rx.take_message() {
+                    Some(msg) => Ok(Some(msg)),
+                    None => Err(rx.truncated().into()),
+                },
+            };
+        }
+
+        if framing.interleave_depth() > 1 {
+            if packet.packet.len() < framing.line_code.size() {
+                return Err(rx.truncated().into());
+            }
+            block.push(framing.line_code.decode(&packet.packet), &packet);
+            if block.packets.len() == framing.interleave_depth() {
+                block.flush(&mut rx, framing)?;
+            }
+        } else {
+            rx.append_coded(framing, &packet)?;
+        }
+        if rx.state() == RxState::Complete {
+            return Ok(rx.take_message());
+        }
+        timeout = timing.packet_timeout_us;
+    }
+}
+
+// Send a message and, when it has the listens flag, wait for the reply
+// within LinkTiming::listen_window_us
+pub async fn send_request<R, M, const N: usize>(
+    radio: &mut R,
+    sender: Sender<'_, M>,
+    framing: Framing<'_>,
+    timing: &LinkTiming,
+) -> Result<Option<RxMessage<N>>, LinkError<<R as RadioTx>::Error>>
+where
+    R: RadioTx + RadioRx<Error = <R as RadioTx>::Error>,
+    M: TxMessage,
+{
+    let will_listen = sender.message().will_listen();
+    send_message(radio, sender, framing, timing)
+        .await
+        .map_err(LinkError::Radio)?;
+
+    if !will_listen {
+        return Ok(None);
+    }
+
+    receive_message_framed(radio, &framing, timing, timing.listen_window_us).await
+}
+
+// send_request as the next attempt of retries, see send_message_attempt
+pub async fn send_request_attempt<R, M, const N: usize, const B: usize>(
+    radio: &mut R,
+    sender: Sender<'_, M>,
+    framing: Framing<'_>,
+    timing: &LinkTiming,
+    limiter: &mut DutyCycleLimiter<B>,
+    retries: &mut Retries,
+    now_ms: u64,
+) -> Result<Option<RxMessage<N>>, LinkError<<R as RadioTx>::Error>>
+where
+    R: RadioTx + RadioRx<Error = <R as RadioTx>::Error>,
+    M: TxMessage,
+{
+    retries
+        .attempt(limiter, now_ms)
+        .map_err(LinkError::DutyCycle)?;
+    send_request(radio, sender, framing, timing).await
+}
+
+// Frames of an interleaved block with the rssi and lna they arrived with
+#[derive(Default)]
+struct Block {
+    packets: Vec<PacketWithInterleave, MAX_INTERLEAVE_DEPTH>,
+    signal: Vec<(u8, u8), MAX_INTERLEAVE_DEPTH>,
+}
+
+impl Block {
+    fn push(&mut self, p: PacketWithInterleave, raw: &RxPacket) {
+        self.packets.push(p).ignore();
+        self.signal.push((raw.rssi, raw.lna)).ignore();
+    }
+
+    // Packets after the last one of a message are ignored
+    fn flush<const N: usize>(
+        &mut self,
+        rx: &mut RxMessageDecoder<N>,
+        framing: &Framing,
+    ) -> Result<(), RxDecodeError> {
+        deinterleave(&mut self.packets);
+        for (p, (rssi, lna)) in self.packets.iter().zip(&self.signal) {
+            if rx.state() == RxState::Complete {
+                break;
+            }
+            let dec = GolayDecoderResult::from(&framing.packet_interleave.deinterleave(p));
+            rx.append_received(&dec, *rssi, *lna)?;
+        }
+        self.packets.clear();
+        self.signal.clear();
+        Ok(())
+    }
+}
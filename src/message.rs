@@ -5,7 +5,7 @@ use ignore_result::Ignore as _;
 use ufmt::derive::uDebug;
 
 use crate::{
-    tx::MessageSender,
+    tx::{MessageRef, MessageSender},
     util::{encode_varlength, IntoLeastSigByte},
 };
 
@@ -36,6 +36,17 @@ impl<const N: usize> Message<N> {
         MessageSender::new(self)
     }
 
+    // Borrow the payload for sending without a copy
+    pub fn as_message_ref(&self) -> MessageRef<'_> {
+        MessageRef {
+            version: self.version,
+            data: &self.data,
+            source_address: self.source_address,
+            packet_type: self.packet_type,
+            will_listen: self.will_listen,
+        }
+    }
+
     pub fn add<T: Shr<usize, Output = T> + Into<IntoLeastSigByte> + Copy>(&mut self, v: T) {
         self.data.add(v);
     }
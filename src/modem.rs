@@ -0,0 +1,399 @@
+// Software 2-FSK and OOK modem for baseband IQ sample files, for testing
+// the physical layer without radio hardware.
+//
+// The modulator turns framed radio packets (see Framing) into complex
+// baseband samples. The demodulator recovers the bits from the samples
+// and hands them to FrameSync, which finds the packets after the sync
+// word. Sample files use the formats of common SDR tools:
+//
+//   CU8   interleaved I/Q, unsigned 8 bit with 127.5 as zero (rtl_sdr)
+//   CS16  interleaved I/Q, signed 16 bit little endian
+
+use core::f64::consts::TAU;
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    vec::Vec,
+};
+
+use crate::frame::FrameSync;
+use crate::line::LineCoded;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modulation {
+    // A one is sent at +deviation_hz from the carrier, a zero at -deviation_hz
+    Fsk { deviation_hz: f64 },
+    // A one is sent as carrier, a zero as silence
+    Ook,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    Cu8,
+    Cs16,
+}
+
+impl SampleFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cu8" => Some(SampleFormat::Cu8),
+            "cs16" => Some(SampleFormat::Cs16),
+            _ => None,
+        }
+    }
+
+    // Bytes per complex sample
+    pub fn sample_size(&self) -> usize {
+        match self {
+            SampleFormat::Cu8 => 2,
+            SampleFormat::Cs16 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModemConfig {
+    pub sample_rate: u32,
+    pub bitrate: u32,
+    pub modulation: Modulation,
+}
+
+impl Default for ModemConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 1_000_000,
+            bitrate: 50_000,
+            modulation: Modulation::Fsk {
+                deviation_hz: 25_000.0,
+            },
+        }
+    }
+}
+
+impl ModemConfig {
+    // Does not have to be a whole number
+    pub fn samples_per_bit(&self) -> f64 {
+        self.sample_rate as f64 / self.bitrate as f64
+    }
+}
+
+// Complex baseband sample, full scale is 1.0
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Iq {
+    pub i: f32,
+    pub q: f32,
+}
+
+// Carrier amplitude, leaves room for noise before the samples clip
+const AMPLITUDE: f64 = 0.7;
+
+// Fraction of the timing error corrected at every transition
+const CLOCK_GAIN: f64 = 0.2;
+
+// A transition needs this fraction of a clean bit in the other direction
+const HYSTERESIS: f32 = 0.3;
+
+// Time constant of the OOK level tracking, in bits
+const OOK_LEVEL_BITS: f64 = 32.0;
+
+pub struct Modulator {
+    config: ModemConfig,
+    // FSK phase, continuous over all bits
+    phase: f64,
+    // Fraction of a sample left over from the previous bit
+    time: f64,
+}
+
+impl Modulator {
+    pub fn new(config: ModemConfig) -> Self {
+        Self {
+            config,
+            phase: 0.0,
+            time: 0.0,
+        }
+    }
+
+    // Append the samples for the bytes, sent MSb first
+    pub fn modulate(&mut self, data: &[u8], out: &mut Vec<Iq>) {
+        for b in data {
+            for bit in (0..8).rev() {
+                self.bit(b >> bit & 1 == 1, out);
+            }
+        }
+    }
+
+    // Append a gap without signal
+    pub fn silence(&mut self, samples: usize, out: &mut Vec<Iq>) {
+        out.extend(core::iter::repeat_n(Iq::default(), samples));
+    }
+
+    fn bit(&mut self, one: bool, out: &mut Vec<Iq>) {
+        // Bit edges fall between samples when the sample rate is not
+        // a multiple of the bitrate
+        self.time += self.config.samples_per_bit();
+        let samples = self.time.floor();
+        self.time -= samples;
+
+        for _ in 0..samples as usize {
+            let s = match self.config.modulation {
+                Modulation::Fsk { deviation_hz } => {
+                    let f = if one { deviation_hz } else { -deviation_hz };
+                    self.phase = (self.phase + TAU * f / self.config.sample_rate as f64) % TAU;
+                    Iq {
+                        i: (AMPLITUDE * self.phase.cos()) as f32,
+                        q: (AMPLITUDE * self.phase.sin()) as f32,
+                    }
+                }
+                Modulation::Ook if one => Iq {
+                    i: AMPLITUDE as f32,
+                    q: 0.0,
+                },
+                Modulation::Ook => Iq::default(),
+            };
+            out.push(s);
+        }
+    }
+}
+
+pub fn write_iq<W: Write>(mut w: W, format: SampleFormat, samples: &[Iq]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(samples.len() * format.sample_size());
+    for s in samples {
+        for v in [s.i, s.q] {
+            let v = v.clamp(-1.0, 1.0);
+            match format {
+                SampleFormat::Cu8 => buf.push((v * 127.5 + 127.5).round() as u8),
+                SampleFormat::Cs16 => {
+                    buf.extend_from_slice(&((v * 32767.0).round() as i16).to_le_bytes())
+                }
+            }
+        }
+    }
+    w.write_all(&buf)
+}
+
+// Reads all samples, a partial sample at the end is dropped
+pub fn read_iq<R: Read>(mut r: R, format: SampleFormat) -> io::Result<Vec<Iq>> {
+    let mut buf = Vec::new();
+    r.read_to_end(&mut buf)?;
+
+    let cu8 = |b: u8| (b as f32 - 127.5) / 127.5;
+    let cs16 = |c: &[u8]| i16::from_le_bytes([c[0], c[1]]) as f32 / 32767.0;
+    Ok(buf
+        .chunks_exact(format.sample_size())
+        .map(|c| match format {
+            SampleFormat::Cu8 => Iq {
+                i: cu8(c[0]),
+                q: cu8(c[1]),
+            },
+            SampleFormat::Cs16 => Iq {
+                i: cs16(&c[0..2]),
+                q: cs16(&c[2..4]),
+            },
+        })
+        .collect())
+}
+
+// Recovers the bits from the samples. The soft decision of every sample
+// (frequency for FSK, amplitude over the decision level for OOK) is
+// averaged over one bit. Transitions of the average pull the bit clock
+// towards them, over runs of equal bits it free runs.
+pub struct Demodulator {
+    config: ModemConfig,
+    last: Iq,
+
+    window: VecDeque<f32>,
+    window_len: usize,
+    sum: f32,
+
+    // OOK decision level, half way between the average
+    // amplitudes of the ones and the zeros
+    high: f32,
+    low: f32,
+    level_alpha: f32,
+    attack: f32,
+
+    level: bool,
+    // Samples since the last bit edge
+    phase: f64,
+}
+
+impl Demodulator {
+    pub fn new(config: ModemConfig) -> Self {
+        let spb = config.samples_per_bit();
+        Self {
+            config,
+            last: Iq::default(),
+            window: VecDeque::new(),
+            window_len: (spb.round() as usize).max(1),
+            sum: 0.0,
+            high: 0.0,
+            low: 0.0,
+            level_alpha: (1.0 / (OOK_LEVEL_BITS * spb)) as f32,
+            attack: (1.0 / spb) as f32,
+            level: false,
+            phase: 0.0,
+        }
+    }
+
+    fn soft(&mut self, s: Iq) -> f32 {
+        let v = match self.config.modulation {
+            // Phase change since the previous sample
+            Modulation::Fsk { .. } => {
+                let l = self.last;
+                (l.i * s.q - l.q * s.i).atan2(l.i * s.i + l.q * s.q)
+            }
+            Modulation::Ook => {
+                let mag = s.i.hypot(s.q);
+                let mid = (self.high + self.low) / 2.0;
+                // Levels follow the signal within a bit when it gets stronger
+                // or weaker, and slowly otherwise
+                if mag > self.high {
+                    self.high += (mag - self.high) * self.attack;
+                } else if mag > mid {
+                    self.high += (mag - self.high) * self.level_alpha;
+                } else if mag < self.low {
+                    self.low += (mag - self.low) * self.attack;
+                } else {
+                    self.low += (mag - self.low) * self.level_alpha;
+                }
+                mag - mid
+            }
+        };
+        self.last = s;
+
+        self.window.push_back(v);
+        self.sum += v;
+        if self.window.len() > self.window_len {
+            self.sum -= self.window.pop_front().unwrap_or_default();
+        }
+        self.sum / self.window.len() as f32
+    }
+
+    // Returns a bit when one was completed by this sample
+    pub fn push(&mut self, s: Iq) -> Option<bool> {
+        let spb = self.config.samples_per_bit();
+        let v = self.soft(s);
+
+        let hysteresis = HYSTERESIS * self.expected();
+        if (self.level && v < -hysteresis) || (!self.level && v > hysteresis) {
+            self.level = !self.level;
+            // Transitions are expected at the bit edges, a noise glitch
+            // only moves the clock part of the way
+            let error = if self.phase < spb / 2.0 {
+                self.phase
+            } else {
+                self.phase - spb
+            };
+            self.phase -= error * CLOCK_GAIN;
+        }
+
+        let before = self.phase;
+        self.phase += 1.0;
+        if self.phase >= spb {
+            self.phase -= spb;
+        }
+
+        // Half way between the edges the average covers exactly one bit
+        (before < spb / 2.0 && self.phase >= spb / 2.0).then_some(v > 0.0)
+    }
+
+    // Smoothed soft value of a clean bit
+    fn expected(&self) -> f32 {
+        match self.config.modulation {
+            Modulation::Fsk { deviation_hz } => {
+                (TAU * deviation_hz / self.config.sample_rate as f64) as f32
+            }
+            Modulation::Ook => (self.high - self.low) / 2.0,
+        }
+    }
+
+    // Demodulate the samples and return the packets found by sync
+    pub fn receive(&mut self, samples: &[Iq], sync: &mut FrameSync) -> Vec<LineCoded> {
+        samples
+            .iter()
+            .filter_map(|s| self.push(*s))
+            .filter_map(|bit| sync.push(bit))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(modulation: Modulation) -> ModemConfig {
+        ModemConfig {
+            sample_rate: 250_000,
+            bitrate: 9_600,
+            modulation,
+        }
+    }
+
+    fn bits(config: ModemConfig, data: &[u8]) -> Vec<bool> {
+        let mut samples = Vec::new();
+        let mut m = Modulator::new(config);
+        m.silence(100, &mut samples);
+        m.modulate(data, &mut samples);
+
+        let mut d = Demodulator::new(config);
+        samples.iter().filter_map(|s| d.push(*s)).collect()
+    }
+
+    fn to_bits(data: &[u8]) -> Vec<bool> {
+        data.iter()
+            .flat_map(|b| (0..8).rev().map(move |bit| b >> bit & 1 == 1))
+            .collect()
+    }
+
+    #[test]
+    fn test_modulator() {
+        let config = config(Modulation::Fsk {
+            deviation_hz: 20_000.0,
+        });
+        let mut samples = Vec::new();
+        Modulator::new(config).modulate(&[0xff; 96], &mut samples);
+        // 26.04 samples per bit
+        assert_eq!(samples.len(), 20_000);
+        assert!(samples
+            .iter()
+            .all(|s| ((s.i.hypot(s.q)) as f64 - AMPLITUDE).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_bits() {
+        let data = [0xaa, 0xaa, 0x2d, 0xd4, 0x00, 0xff, 0x0f, 0x35];
+        for modulation in [
+            Modulation::Fsk {
+                deviation_hz: 20_000.0,
+            },
+            Modulation::Ook,
+        ] {
+            let rx = bits(config(modulation), &data);
+            let tx = to_bits(&data);
+            // The bit clock locks during the first preamble byte, the
+            // last bit is only complete after the end of the samples
+            let tail = &tx[8..tx.len() - 1];
+            assert!(rx.windows(tail.len()).any(|w| w == tail), "{modulation:?}");
+        }
+    }
+
+    #[test]
+    fn test_sample_formats() {
+        let samples = [
+            Iq { i: 0.5, q: -0.5 },
+            Iq { i: 1.0, q: -1.0 },
+            Iq { i: 0.0, q: 2.0 },
+        ];
+        for (format, tolerance) in [(SampleFormat::Cu8, 0.005), (SampleFormat::Cs16, 1e-4)] {
+            let mut file = Vec::new();
+            write_iq(&mut file, format, &samples).unwrap();
+            assert_eq!(file.len(), samples.len() * format.sample_size());
+
+            let read = read_iq(&file[..], format).unwrap();
+            for (a, b) in read.iter().zip(samples) {
+                assert!((a.i - b.i.clamp(-1.0, 1.0)).abs() < tolerance);
+                assert!((a.q - b.q.clamp(-1.0, 1.0)).abs() < tolerance);
+            }
+        }
+    }
+}
@@ -4,6 +4,12 @@ use ufmt::derive::uDebug;
 
 use crate::dc::{balance, strip};
 
+// Size of a single packet as seen by the radio, with the default
+// 6b/8b line code
+pub const RADIO_PACKET_SIZE: usize = 32;
+// Largest radio packet of all line codes, see line::LineCode
+pub const MAX_RADIO_PACKET_SIZE: usize = 48;
+
 #[cfg(feature = "legacy")]
 #[derive(defmt::Format, uDebug, Clone, Copy, Eq, PartialEq, Debug, Default)]
 pub struct PacketStatusLegacy {
@@ -254,6 +260,9 @@ pub struct GolayDecoderResult {
     pub data: PacketData,
     pub parity_errors: usize,
     pub errors: usize,
+    // Received codewords before error correction,
+    // the CRC guided repair looks for alternative decodings of these
+    pub codewords: [u32; 8],
 }
 
 impl PacketWithGolay {
@@ -267,9 +276,15 @@ impl PacketWithGolay {
         Self { data: [0; 24] }
     }
 
+    pub fn data(&self) -> [u8; 24] {
+        self.data
+    }
+
     const POLY: u32 = 0xAE3;
 
-    fn apply_golay(c: u16) -> u32 {
+    // Encode a 12 bit word, the word is kept in the low 12 bits, followed
+    // by 11 check bits and the overall parity in bit 23
+    pub fn apply_golay(c: u16) -> u32 {
         debug_assert_eq!(c >> 12, 0);
 
         let s = Self::syndrome(c.into());
@@ -298,7 +313,7 @@ impl PacketWithGolay {
     fn parity_24b(cw: u32) -> u32 {
         let mut parity = (cw >> 16) as u8;
         parity ^= (cw >> 8) as u8;
-        parity ^= (cw >> 0) as u8;
+        parity ^= cw as u8;
 
         parity = (parity >> 4) ^ parity;
         parity = (parity >> 2) ^ parity;
@@ -342,7 +357,9 @@ impl PacketWithGolay {
         cw & 0x7fffff
     }
 
-    fn undo_golay(raw: u32) -> (u16, usize, bool) {
+    // Correct a received codeword, returns the 12 bit word, the number
+    // of corrected bits and whether the overall parity matched
+    pub fn undo_golay(raw: u32) -> (u16, usize, bool) {
         //golay::decode(raw).unwrap_or((0_u16, 12))
         let mut mask: u32 = 0x1; /* mask for bit flipping, start with Lsb */
 
@@ -404,46 +421,45 @@ impl PacketWithGolay {
 
         ((cwsaver & 0xfff) as u16, 0, Self::parity_24b(cwsaver) == 0) /* return original if no corrections */
     }
+
+    // Collect the other likely decodings of a received codeword.
+    // Every single bit flip of the received data is decoded again,
+    // this finds the codewords within distance 4.
+    pub(crate) fn alternatives(raw: u32, out: &mut Vec<u16, 24>) {
+        let (original, _, _) = Self::undo_golay(raw);
+        out.clear();
+
+        for bit in 0..24 {
+            let (c, _, parity) = Self::undo_golay(raw ^ (1 << bit));
+            if parity && c != original && !out.contains(&c) {
+                out.push(c).ignore();
+            }
+        }
+    }
 }
 
-impl From<&PacketWithGolay> for GolayDecoderResult {
-    // Convert Golay encoded data into the final readable PacketData
-    // Make sure the p.status is set to whatever the previous packet reported
-    // to make sure the status type autodetection works correctly
-    fn from(golay: &PacketWithGolay) -> Self {
-        let mut ret = GolayDecoderResult::default();
+impl GolayDecoderResult {
+    // Assemble the packet from the eight decoded 12 bit words
+    pub(crate) fn from_words(
+        words: &[u16; 8],
+        codewords: &[u32; 8],
+        errors: usize,
+        parity_errors: usize,
+    ) -> Self {
+        let mut ret = GolayDecoderResult {
+            errors,
+            parity_errors,
+            codewords: *codewords,
+            ..Default::default()
+        };
 
         let mut buff = [0_u8; 12];
 
-        let mut i_src = 0;
-        let mut i_dst = 0;
-
-        while i_src < golay.data.len() {
-            let src1 = ((golay.data[i_src] as u32) << 16)
-                + ((golay.data[i_src + 1] as u32) << 8)
-                + (golay.data[i_src + 2] as u32);
-            let src2 = ((golay.data[i_src + 3] as u32) << 16)
-                + ((golay.data[i_src + 4] as u32) << 8)
-                + (golay.data[i_src + 5] as u32);
-
-            let (dst1, err1, parity1) = PacketWithGolay::undo_golay(src1);
-            let (dst2, err2, parity2) = PacketWithGolay::undo_golay(src2);
-
-            if !parity1 {
-                ret.parity_errors += 1;
-            }
-            if !parity2 {
-                ret.parity_errors += 1;
-            }
-
+        for (i_dst, pair) in (0..buff.len()).step_by(3).zip(words.chunks(2)) {
+            let (dst1, dst2) = (pair[0], pair[1]);
             buff[i_dst] = (dst1 >> 4) as u8; // [12:4]
             buff[i_dst + 1] = (((dst1 & 0xf) << 4) as u8) + (((dst2 & 0xf00) >> 8) as u8); // [4:0] [12:8]
             buff[i_dst + 2] = dst2 as u8; // [8:0]
-
-            ret.errors += err1 + err2;
-
-            i_src += 6;
-            i_dst += 3;
         }
 
         // The destination is sized properly to take 11B
@@ -457,6 +473,36 @@ impl From<&PacketWithGolay> for GolayDecoderResult {
     }
 }
 
+impl From<&PacketWithGolay> for GolayDecoderResult {
+    // Convert Golay encoded data into the final readable PacketData
+    // Make sure the p.status is set to whatever the previous packet reported
+    // to make sure the status type autodetection works correctly
+    fn from(golay: &PacketWithGolay) -> Self {
+        let mut words = [0_u16; 8];
+        let mut codewords = [0_u32; 8];
+        let mut errors = 0;
+        let mut parity_errors = 0;
+
+        for ((word, codeword), src) in words
+            .iter_mut()
+            .zip(codewords.iter_mut())
+            .zip(golay.data.chunks(3))
+        {
+            *codeword = ((src[0] as u32) << 16) + ((src[1] as u32) << 8) + (src[2] as u32);
+
+            let (dst, err, parity) = PacketWithGolay::undo_golay(*codeword);
+            if !parity {
+                parity_errors += 1;
+            }
+
+            *word = dst;
+            errors += err;
+        }
+
+        GolayDecoderResult::from_words(&words, &codewords, errors, parity_errors)
+    }
+}
+
 impl From<&PacketData> for PacketWithGolay {
     fn from(p: &PacketData) -> Self {
         let mut ret = PacketWithGolay { data: [0u8; 24] };
@@ -475,11 +521,11 @@ impl From<&PacketData> for PacketWithGolay {
 
             ret.data[i_dst] = (dst1 >> 16) as u8;
             ret.data[i_dst + 1] = (dst1 >> 8) as u8;
-            ret.data[i_dst + 2] = (dst1 >> 0) as u8;
+            ret.data[i_dst + 2] = dst1 as u8;
 
             ret.data[i_dst + 3] = (dst2 >> 16) as u8;
             ret.data[i_dst + 4] = (dst2 >> 8) as u8;
-            ret.data[i_dst + 5] = (dst2 >> 0) as u8;
+            ret.data[i_dst + 5] = dst2 as u8;
 
             i_src += 3;
             i_dst += 6;
@@ -499,6 +545,10 @@ impl PacketWithInterleave {
         Self { data: [0; 24] }
     }
 
+    pub fn data(&self) -> [u8; 24] {
+        self.data
+    }
+
     #[inline(always)]
     fn g3byte(msb: u8, isb: u8, lsb: u8) -> u32 {
         ((msb as u32) << 16) + ((isb as u32) << 8) + (lsb as u32)
@@ -513,6 +563,18 @@ impl PacketWithInterleave {
     }
 }
 
+impl From<[u8; 24]> for PacketWithInterleave {
+    fn from(data: [u8; 24]) -> Self {
+        Self { data }
+    }
+}
+
+impl From<[u8; 24]> for PacketWithGolay {
+    fn from(data: [u8; 24]) -> Self {
+        Self { data }
+    }
+}
+
 impl From<&PacketWithInterleave> for PacketWithGolay {
     fn from(p: &PacketWithInterleave) -> Self {
         let mut ret = PacketWithGolay::default();
@@ -624,6 +686,13 @@ impl PacketWithoutDC {
         self.data
     }
 
+    // Decode received radio data
+    pub fn decode(&self) -> GolayDecoderResult {
+        let p = PacketWithInterleave::from(self);
+        let p = PacketWithGolay::from(&p);
+        GolayDecoderResult::from(&p)
+    }
+
     fn balance_dc(src: u8) -> u8 {
         balance(src)
     }
@@ -686,6 +755,21 @@ impl From<&PacketWithInterleave> for PacketWithoutDC {
     }
 }
 
+// V2 packet fixture shared by the unit tests of the other modules
+#[cfg(test)]
+pub(crate) fn test_v2_packet(data: &[u8]) -> PacketData {
+    PacketData {
+        data: Vec::from_slice(data).expect("Not enough space in vector"),
+        status: PacketStatus::V2(PacketStatusV2::default()),
+    }
+}
+
+// Payload of the packet fixture used by the decoder tests
+#[cfg(test)]
+pub(crate) const TEST_DATA: [u8; 11] = [
+    0x81, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A,
+];
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,361 @@
+// pcap export of received packets for Wireshark.
+//
+// The packets use the user link type DLT_USER0. Every pcap record holds
+// the reception metadata, the raw radio frame and the decoded packet:
+//
+//   offset  size  field
+//        0     1  format version
+//        1     1  status kind, see KIND_*
+//        2     1  corrected bits
+//        3     1  parity errors
+//        4     1  rssi
+//        5     1  lna
+//        6     1  radio frame length, at most 48
+//        7     1  reserved
+//        8     4  frequency_hz, little endian
+//       12     4  gateway_id, little endian
+//       16    48  radio frame, zero padded
+//       64    11  decoded data bytes
+//       75     1  status byte
+//
+// The status kind is the status as the receiver decoded it in the context
+// of the previous packets, the status byte alone is ambiguous. lua_dissector
+// generates a Wireshark dissector for this layout.
+
+use crate::capture::{ByteSink, CaptureRecord};
+use crate::packet::{GolayDecoderResult, PacketStatus, MAX_RADIO_PACKET_SIZE};
+
+pub const DLT_USER0: u32 = 147;
+pub const PCAP_HEADER_SIZE: usize = 24;
+pub const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+pub const LASO_PCAP_VERSION: u8 = 2;
+pub const LASO_RECORD_SIZE: usize = 76;
+
+const FRAME_OFFSET: usize = 16;
+const DATA_OFFSET: usize = FRAME_OFFSET + MAX_RADIO_PACKET_SIZE;
+const DATA_SIZE: usize = 11;
+const STATUS_OFFSET: usize = DATA_OFFSET + DATA_SIZE;
+
+pub const KIND_UNKNOWN: u8 = 0;
+pub const KIND_V2: u8 = 1;
+pub const KIND_CRC8P: u8 = 2;
+pub const KIND_DATA: u8 = 3;
+pub const KIND_LEGACY: u8 = 4;
+pub const KIND_RAW: u8 = 5;
+
+pub fn status_kind(status: PacketStatus) -> u8 {
+    match status {
+        #[cfg(feature = "legacy")]
+        PacketStatus::Legacy(_) => KIND_LEGACY,
+        PacketStatus::V2(_) => KIND_V2,
+        PacketStatus::CRC8P(_) => KIND_CRC8P,
+        PacketStatus::Data(_) => KIND_DATA,
+        PacketStatus::Raw(_) => KIND_RAW,
+        PacketStatus::Unknown | PacketStatus::Internal => KIND_UNKNOWN,
+    }
+}
+
+// Little endian pcap file header, microsecond timestamps
+pub fn pcap_header() -> [u8; PCAP_HEADER_SIZE] {
+    let mut out = [0; PCAP_HEADER_SIZE];
+    out[0..4].copy_from_slice(&0xa1b2_c3d4_u32.to_le_bytes());
+    out[4..6].copy_from_slice(&2_u16.to_le_bytes());
+    out[6..8].copy_from_slice(&4_u16.to_le_bytes());
+    // Time zone and accuracy stay zero
+    out[16..20].copy_from_slice(&(LASO_RECORD_SIZE as u32).to_le_bytes());
+    out[20..24].copy_from_slice(&DLT_USER0.to_le_bytes());
+    out
+}
+
+// The LASO part of a pcap record. The status is the one returned by the
+// receiver for this packet, the data and status byte come from dec.
+pub fn encode_record(
+    record: &CaptureRecord,
+    dec: &GolayDecoderResult,
+    status: PacketStatus,
+) -> [u8; LASO_RECORD_SIZE] {
+    let mut out = [0; LASO_RECORD_SIZE];
+    out[0] = LASO_PCAP_VERSION;
+    out[1] = status_kind(status);
+    out[2] = dec.errors.min(u8::MAX as usize) as u8;
+    out[3] = dec.parity_errors as u8;
+    out[4] = record.rssi;
+    out[5] = record.lna;
+    out[6] = record.frame.len() as u8;
+    out[8..12].copy_from_slice(&record.frequency_hz.to_le_bytes());
+    out[12..16].copy_from_slice(&record.gateway_id.to_le_bytes());
+    out[FRAME_OFFSET..FRAME_OFFSET + record.frame.len()].copy_from_slice(&record.frame);
+
+    let data = &dec.data.data[..dec.data.data.len().min(DATA_SIZE)];
+    out[DATA_OFFSET..DATA_OFFSET + data.len()].copy_from_slice(data);
+    out[STATUS_OFFSET] = status.encode();
+    out
+}
+
+pub struct PcapWriter<S: ByteSink> {
+    sink: S,
+}
+
+impl<S: ByteSink> PcapWriter<S> {
+    // Start a new pcap file, writes the file header
+    pub fn new(mut sink: S) -> Result<Self, S::Error> {
+        sink.write_all(&pcap_header())?;
+        Ok(Self { sink })
+    }
+
+    pub fn write(
+        &mut self,
+        record: &CaptureRecord,
+        dec: &GolayDecoderResult,
+        status: PacketStatus,
+    ) -> Result<(), S::Error> {
+        let mut header = [0; PCAP_RECORD_HEADER_SIZE];
+        let secs = (record.timestamp_us / 1_000_000) as u32;
+        let usecs = (record.timestamp_us % 1_000_000) as u32;
+        header[0..4].copy_from_slice(&secs.to_le_bytes());
+        header[4..8].copy_from_slice(&usecs.to_le_bytes());
+        header[8..12].copy_from_slice(&(LASO_RECORD_SIZE as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(LASO_RECORD_SIZE as u32).to_le_bytes());
+
+        self.sink.write_all(&header)?;
+        self.sink.write_all(&encode_record(record, dec, status))
+    }
+
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+// Wireshark Lua dissector for the records written by PcapWriter, load it
+// with `wireshark -X lua_script:laso.lua capture.pcap`
+#[cfg(feature = "std")]
+pub fn lua_dissector() -> std::string::String {
+    std::format!(
+        r#"-- LASO dissector for pcap files written by laso_packet {crate_version}
+-- Generated, do not edit. Load with: wireshark -X lua_script:laso.lua capture.pcap
+
+local laso = Proto("laso", "LASO")
+
+local kinds = {{
+    [{unknown}] = "Unknown",
+    [{v2}] = "V2",
+    [{crc8p}] = "CRC8P",
+    [{data}] = "Data",
+    [{legacy}] = "Legacy",
+    [{raw}] = "Raw",
+}}
+
+local f = laso.fields
+f.version = ProtoField.uint8("laso.version", "Format version")
+f.kind = ProtoField.uint8("laso.kind", "Status kind", base.DEC, kinds)
+f.errors = ProtoField.uint8("laso.errors", "Corrected bits")
+f.parity_errors = ProtoField.uint8("laso.parity_errors", "Parity errors")
+f.rssi = ProtoField.uint8("laso.rssi", "RSSI")
+f.lna = ProtoField.uint8("laso.lna", "LNA")
+f.frame_len = ProtoField.uint8("laso.frame_len", "Frame length")
+f.frequency = ProtoField.uint32("laso.frequency", "Frequency (Hz)")
+f.gateway = ProtoField.uint32("laso.gateway", "Gateway", base.HEX)
+f.frame = ProtoField.bytes("laso.frame", "Radio frame")
+f.data = ProtoField.bytes("laso.data", "Data")
+f.status = ProtoField.uint8("laso.status", "Status", base.HEX)
+f.more = ProtoField.bool("laso.status.more", "More packets (not short)", 8, nil, 0x01)
+f.naked = ProtoField.bool("laso.status.naked", "Naked", 8, nil, 0x02)
+f.listens = ProtoField.bool("laso.status.listens", "Listens", 8, nil, 0x08)
+f.packet_type = ProtoField.uint32("laso.type", "Packet type")
+f.source = ProtoField.uint32("laso.source", "Source address", base.HEX)
+f.crc8 = ProtoField.uint8("laso.crc8", "CRC8", base.HEX)
+f.crc8p = ProtoField.uint8("laso.crc8p", "CRC8P", base.HEX)
+f.payload = ProtoField.bytes("laso.payload", "Payload")
+
+-- Variable length number, 7 bits per byte, least significant first
+local function varint(buf, offset, limit)
+    local val, shift, len = 0, 0, 0
+    while shift < 16 and offset + len < limit do
+        local b = buf(offset + len, 1):uint()
+        val = val + bit.lshift(bit.band(b, 0x7f), shift)
+        shift = shift + 7
+        len = len + 1
+        if bit.band(b, 0x80) == 0 then
+            break
+        end
+    end
+    return val, len
+end
+
+local function add_range(tree, field, buf, offset, len)
+    if len > 0 then
+        tree:add(field, buf(offset, len))
+    end
+end
+
+function laso.dissector(buf, pinfo, tree)
+    if buf:len() < {size} then
+        return 0
+    end
+    pinfo.cols.protocol = "LASO"
+
+    local t = tree:add(laso, buf(0, {size}))
+    t:add(f.version, buf(0, 1))
+    t:add(f.kind, buf(1, 1))
+    t:add(f.errors, buf(2, 1))
+    t:add(f.parity_errors, buf(3, 1))
+    t:add(f.rssi, buf(4, 1))
+    t:add(f.lna, buf(5, 1))
+    t:add(f.frame_len, buf(6, 1))
+    t:add_le(f.frequency, buf(8, 4))
+    t:add_le(f.gateway, buf(12, 4))
+    t:add(f.frame, buf({frame}, {frame_size}))
+    t:add(f.data, buf({data_offset}, {data_size}))
+
+    local kind = buf(1, 1):uint()
+    local status = buf({status}, 1)
+    local st = t:add(f.status, status)
+    local info = kinds[kind] or "Unknown"
+
+    local first = {data_offset}
+    local limit = {data_offset} + {data_size}
+    if kind == {v2} then
+        st:add(f.more, status)
+        st:add(f.naked, status)
+        st:add(f.listens, status)
+
+        local s = status:uint()
+        local naked = bit.band(s, 0x02) ~= 0
+        local short = bit.band(s, 0x01) == 0
+        if naked then
+            info = info .. " naked"
+        end
+        if short then
+            info = info .. " short"
+        end
+        if bit.band(s, 0x08) ~= 0 then
+            info = info .. " listens"
+        end
+
+        local offset = first
+        if not naked then
+            local packet_type, len = varint(buf, offset, limit)
+            if len > 0 then
+                t:add(f.packet_type, buf(offset, len), packet_type)
+                info = info .. string.format(" type=0x%x", packet_type)
+            end
+            offset = offset + len
+        end
+        local source, len = varint(buf, offset, limit)
+        if len > 0 then
+            t:add(f.source, buf(offset, len), source)
+            info = info .. string.format(" source=0x%x", source)
+        end
+        offset = offset + len
+
+        -- Short V2 packets end with the CRC8 of the message
+        if short and not naked then
+            limit = limit - 1
+            t:add(f.crc8, buf(limit, 1))
+        end
+        add_range(t, f.payload, buf, offset, limit - offset)
+    elseif kind == {crc8p} then
+        t:add(f.crc8p, status)
+        add_range(t, f.payload, buf, first, limit - first)
+    elseif kind == {data} then
+        -- Naked continuation, the status byte is payload as well
+        add_range(t, f.payload, buf, first, limit - first + 1)
+    else
+        add_range(t, f.payload, buf, first, limit - first)
+    end
+
+    pinfo.cols.info = info
+    return {size}
+end
+
+DissectorTable.get("wtap_encap"):add(wtap.USER0, laso)
+"#,
+        crate_version = env!("CARGO_PKG_VERSION"),
+        unknown = KIND_UNKNOWN,
+        v2 = KIND_V2,
+        crc8p = KIND_CRC8P,
+        data = KIND_DATA,
+        legacy = KIND_LEGACY,
+        raw = KIND_RAW,
+        size = LASO_RECORD_SIZE,
+        frame = FRAME_OFFSET,
+        frame_size = MAX_RADIO_PACKET_SIZE,
+        data_offset = DATA_OFFSET,
+        data_size = DATA_SIZE,
+        status = STATUS_OFFSET,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::message::{Message, MessageVersion};
+    use crate::rx::RxMessageDecoder;
+    use crate::tx::MessageSender;
+
+    #[test]
+    fn test_pcap() {
+        let msg: Message<16> = Message {
+            version: MessageVersion::V2,
+            source_address: 0x1234,
+            packet_type: Some(0x10),
+            data: Vec::from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]).unwrap(),
+            ..Default::default()
+        };
+
+        let mut w = PcapWriter::new(Vec::<u8, 256>::new()).unwrap();
+        let mut rx: RxMessageDecoder<32> = RxMessageDecoder::default();
+        for (n, frame) in MessageSender::new(msg).enumerate() {
+            let record = CaptureRecord {
+                timestamp_us: 1_500_000 + n as u64,
+                frequency_hz: 868_300_000,
+                gateway_id: 7,
+                rssi: 0x40,
+                frame: Vec::from_slice(&frame.data()).unwrap(),
+                ..Default::default()
+            };
+            let dec = frame.decode();
+            let status = rx.append(&dec).unwrap();
+            w.write(&record, &dec, status).unwrap();
+        }
+        let data = w.into_inner();
+        let record = PCAP_RECORD_HEADER_SIZE + LASO_RECORD_SIZE;
+        assert_eq!(data.len(), PCAP_HEADER_SIZE + 2 * record);
+
+        assert_eq!(&data[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(&data[20..24], &[147, 0, 0, 0]);
+
+        // Timestamp and lengths
+        let first = &data[PCAP_HEADER_SIZE..PCAP_HEADER_SIZE + record];
+        assert_eq!(&first[..8], &[1, 0, 0, 0, 0x20, 0xa1, 0x07, 0]);
+        assert_eq!(&first[8..16], &[76, 0, 0, 0, 76, 0, 0, 0]);
+
+        let first = &first[PCAP_RECORD_HEADER_SIZE..];
+        assert_eq!(first[1], KIND_V2);
+        assert_eq!(first[4], 0x40);
+        assert_eq!(first[6], 32);
+        assert_eq!(&first[12..16], &[7, 0, 0, 0]);
+        // Type, source address and the start of the payload
+        assert_eq!(
+            &first[DATA_OFFSET..DATA_OFFSET + 5],
+            &[0x10, 0xb4, 0x24, 1, 2]
+        );
+        assert_eq!(first[STATUS_OFFSET], 0x01);
+
+        let second = &data[PCAP_HEADER_SIZE + record + PCAP_RECORD_HEADER_SIZE..];
+        assert_eq!(second[1], KIND_CRC8P);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lua_dissector() {
+        let lua = lua_dissector();
+        assert!(lua.contains("if buf:len() < 76 then"));
+        assert!(lua.contains("t:add(f.frame, buf(16, 48))"));
+        assert!(lua.contains("local status = buf(75, 1)"));
+        assert!(!lua.contains("{{"));
+    }
+}
@@ -0,0 +1,152 @@
+// Transmission planning: packet count, on-air bytes, airtime and energy
+// of a message computed without encoding it.
+//
+// The computation follows the packet layout produced by MessageSender,
+// it is usable in const context for sizing buffers and duty-cycle budgets
+// at compile time.
+
+use crate::frame::Framing;
+use crate::message::MessageVersion;
+use crate::tx::TxMessage;
+use crate::util::varlength_size;
+
+// Data bytes in a single packet, the status byte is not included
+pub const PACKET_DATA_SIZE: usize = 11;
+
+// Size of the packet type and source address in the first packet
+pub const fn header_size(
+    version: MessageVersion,
+    source_address: u32,
+    packet_type: Option<u32>,
+) -> usize {
+    let type_size = match version {
+        MessageVersion::Naked | MessageVersion::NakedShort => 0,
+        _ => match packet_type {
+            Some(t) => varlength_size(t),
+            // Sent as 0x00
+            None => 1,
+        },
+    };
+    type_size + varlength_size(source_address)
+}
+
+// Payload bytes that fit into the first packet
+pub const fn first_packet_capacity(version: MessageVersion, header_size: usize) -> usize {
+    let capacity = PACKET_DATA_SIZE.saturating_sub(header_size);
+    match version {
+        // The last byte is CRC8
+        MessageVersion::V2Short => capacity.saturating_sub(1),
+        _ => capacity,
+    }
+}
+
+// Payload bytes that fit into each of the follow-up packets
+pub const fn next_packet_capacity(version: MessageVersion) -> usize {
+    match version {
+        // Naked follow-up packets use the status byte for data
+        MessageVersion::Naked | MessageVersion::NakedShort => PACKET_DATA_SIZE + 1,
+        _ => PACKET_DATA_SIZE,
+    }
+}
+
+// Messages that always send at least one follow-up packet
+pub const fn forces_next_packet(version: MessageVersion) -> bool {
+    matches!(version, MessageVersion::V2 | MessageVersion::Naked)
+}
+
+// Number of packets needed for the message, empty messages are not sent
+pub const fn packets(version: MessageVersion, header_size: usize, payload_len: usize) -> usize {
+    if payload_len == 0 {
+        return 0;
+    }
+
+    let rest = payload_len.saturating_sub(first_packet_capacity(version, header_size));
+    let next = rest.div_ceil(next_packet_capacity(version));
+    if forces_next_packet(version) && next == 0 {
+        2
+    } else {
+        1 + next
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxPlan {
+    pub packets: usize,
+    // Bytes on air including the preamble and sync word of every packet
+    pub bytes: usize,
+    // Time on air in microseconds, gaps between packets are not included
+    pub airtime_us: u64,
+}
+
+impl TxPlan {
+    // The bitrate is in bits per second and must not be zero
+    pub const fn new(
+        version: MessageVersion,
+        header_size: usize,
+        payload_len: usize,
+        framing: &Framing,
+        bitrate: u32,
+    ) -> Self {
+        let packets = packets(version, header_size, payload_len);
+        let bytes = packets * framing.frame_size();
+        Self {
+            packets,
+            bytes,
+            airtime_us: (bytes as u64 * 8 * 1_000_000).div_ceil(bitrate as u64),
+        }
+    }
+
+    pub fn for_message<M: TxMessage>(msg: &M, framing: &Framing, bitrate: u32) -> Self {
+        let header = header_size(msg.version(), msg.source_address(), msg.packet_type());
+        Self::new(msg.version(), header, msg.data().len(), framing, bitrate)
+    }
+
+    // Transmit energy in microjoules for the radio TX current (uA)
+    // and supply voltage (mV), rounded up
+    pub const fn energy_uj(&self, tx_current_ua: u32, supply_mv: u32) -> u64 {
+        (tx_current_ua as u64 * supply_mv as u64 * self.airtime_us).div_ceil(1_000_000_000)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_size() {
+        assert_eq!(header_size(MessageVersion::V2, 0x55, None), 2);
+        assert_eq!(header_size(MessageVersion::V2, 0x1234, Some(0x102)), 4);
+        assert_eq!(header_size(MessageVersion::Naked, 0x1234, Some(0x102)), 2);
+    }
+
+    #[test]
+    fn test_packets() {
+        // 11 - 2 header - 1 CRC
+        assert_eq!(packets(MessageVersion::V2Short, 2, 8), 1);
+        assert_eq!(packets(MessageVersion::V2Short, 2, 9), 2);
+        assert_eq!(packets(MessageVersion::V2, 2, 1), 2);
+        assert_eq!(packets(MessageVersion::V2, 2, 20), 2);
+        assert_eq!(packets(MessageVersion::V2, 2, 21), 3);
+        assert_eq!(packets(MessageVersion::NakedShort, 1, 10), 1);
+        assert_eq!(packets(MessageVersion::NakedShort, 1, 22), 2);
+        assert_eq!(packets(MessageVersion::Naked, 1, 23), 3);
+        assert_eq!(packets(MessageVersion::V2, 2, 0), 0);
+    }
+
+    #[test]
+    fn test_airtime() {
+        const PLAN: TxPlan = TxPlan::new(
+            MessageVersion::V2,
+            2,
+            20,
+            &Framing::new(4, &[0x2d, 0xd4]),
+            4800,
+        );
+        assert_eq!(PLAN.packets, 2);
+        assert_eq!(PLAN.bytes, 2 * 38);
+        // 608 bits at 4800 bps
+        assert_eq!(PLAN.airtime_us, 126_667);
+        // 30 mA at 3.3 V
+        assert_eq!(PLAN.energy_uj(30_000, 3300), 12_541);
+    }
+}
@@ -0,0 +1,464 @@
+// Pulse trains for bare ASK/OOK transmitters driven by GPIO timing.
+//
+// A framed radio packet (see Framing) is turned into (level, duration)
+// pulses that a timer interrupt plays back one at a time. Bytes are sent
+// MSb first, every frame is followed by a low gap that tells the receiver
+// the frame is over. Adjacent halves with the same level are merged into
+// a single pulse.
+//
+// PWM: every bit is a high pulse followed by a low pause, a one is a long
+// pulse and a short pause, a zero a short pulse and a long pause.
+//
+// Manchester (G.E. Thomas): a one is high then low, a zero low then high.
+// The receiver aligns on the first high half after the gap, so frames have
+// to start with a one. The 0xAA preamble does that.
+
+use ignore_result::Ignore as _;
+use ufmt::derive::uDebug;
+
+use crate::frame::RadioFrame;
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pulse {
+    pub level: bool,
+    pub duration_us: u32,
+}
+
+impl Pulse {
+    pub const fn high(duration_us: u32) -> Self {
+        Self {
+            level: true,
+            duration_us,
+        }
+    }
+
+    pub const fn low(duration_us: u32) -> Self {
+        Self {
+            level: false,
+            duration_us,
+        }
+    }
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PulseCode {
+    Pwm { short_us: u32, long_us: u32 },
+    Manchester { half_us: u32 },
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PulseConfig {
+    pub code: PulseCode,
+    // Low time after every frame, must be clearly longer than
+    // the longest pulse inside a frame
+    pub gap_us: u32,
+}
+
+impl Default for PulseConfig {
+    fn default() -> Self {
+        Self {
+            code: PulseCode::Pwm {
+                short_us: 250,
+                long_us: 750,
+            },
+            gap_us: 5_000,
+        }
+    }
+}
+
+impl PulseConfig {
+    // Longest pulse inside a frame
+    const fn longest_us(&self) -> u32 {
+        match self.code {
+            PulseCode::Pwm { long_us, .. } => long_us,
+            PulseCode::Manchester { half_us } => half_us.saturating_mul(2),
+        }
+    }
+
+    // Pulses must not be empty, a PWM one longer than a zero, and
+    // the gap longer than any pulse inside a frame
+    pub const fn is_valid(&self) -> bool {
+        let code = match self.code {
+            PulseCode::Pwm { short_us, long_us } => short_us > 0 && short_us < long_us,
+            PulseCode::Manchester { half_us } => half_us > 0,
+        };
+        code && self.gap_us > self.longest_us()
+    }
+
+    // Low pulses from this length on end the frame
+    pub const fn gap_threshold_us(&self) -> u32 {
+        ((self.gap_us as u64 + self.longest_us() as u64) / 2) as u32
+    }
+
+    // Time on air for a frame of the given size, including the gap
+    pub const fn frame_duration_us(&self, bytes: usize) -> u64 {
+        let bit_us = match self.code {
+            PulseCode::Pwm { short_us, long_us } => short_us as u64 + long_us as u64,
+            PulseCode::Manchester { half_us } => 2 * half_us as u64,
+        };
+        (bytes as u64)
+            .saturating_mul(8 * bit_us)
+            .saturating_add(self.gap_us as u64)
+    }
+}
+
+// Pulses of a single frame, ends with the gap
+#[derive(Clone, Debug)]
+pub struct PulseEncoder<D: AsRef<[u8]>> {
+    data: D,
+    config: PulseConfig,
+    // Next half bit, two per bit, the gap follows the last one
+    segment: usize,
+    pending: Option<Pulse>,
+}
+
+impl<D: AsRef<[u8]>> PulseEncoder<D> {
+    // Panics when the config is not valid, see PulseConfig::is_valid
+    pub fn new(data: D, config: PulseConfig) -> Self {
+        assert!(config.is_valid(), "Invalid pulse config");
+        Self {
+            data,
+            config,
+            segment: 0,
+            pending: None,
+        }
+    }
+
+    // Half bits before merging
+    fn segment(&mut self) -> Option<Pulse> {
+        let data = self.data.as_ref();
+        let segments = data.len() * 16;
+        let segment = self.segment;
+        if segment > segments {
+            return None;
+        }
+        self.segment += 1;
+        if segment == segments {
+            return Some(Pulse::low(self.config.gap_us));
+        }
+
+        let bit = segment / 2;
+        let one = data[bit / 8] >> (7 - bit % 8) & 1 == 1;
+        let first = segment % 2 == 0;
+        Some(match self.config.code {
+            PulseCode::Pwm { short_us, long_us } => match (first, one) {
+                (true, true) => Pulse::high(long_us),
+                (true, false) => Pulse::high(short_us),
+                (false, true) => Pulse::low(short_us),
+                (false, false) => Pulse::low(long_us),
+            },
+            PulseCode::Manchester { half_us } => Pulse {
+                level: first == one,
+                duration_us: half_us,
+            },
+        })
+    }
+}
+
+impl<D: AsRef<[u8]>> Iterator for PulseEncoder<D> {
+    type Item = Pulse;
+
+    fn next(&mut self) -> Option<Pulse> {
+        let mut pulse = self.pending.take().or_else(|| self.segment())?;
+        while let Some(next) = self.segment() {
+            if next.level != pulse.level {
+                self.pending = Some(next);
+                break;
+            }
+            pulse.duration_us += next.duration_us;
+        }
+        Some(pulse)
+    }
+}
+
+// Collects the frames from received pulses. Pulses that do not fit the
+// line code drop the frame in progress, a partial byte at the end of a
+// frame is dropped.
+#[derive(Clone, Debug)]
+pub struct PulseDecoder {
+    config: PulseConfig,
+    frame: RadioFrame,
+    byte: u8,
+    bits: u8,
+    // Manchester half bit waiting for its partner
+    half: Option<bool>,
+    // Manchester frame started, the first high pulse after the gap starts it
+    receiving: bool,
+}
+
+impl PulseDecoder {
+    // Panics when the config is not valid, see PulseConfig::is_valid
+    pub fn new(config: PulseConfig) -> Self {
+        assert!(config.is_valid(), "Invalid pulse config");
+        Self {
+            config,
+            frame: RadioFrame::new(),
+            byte: 0,
+            bits: 0,
+            half: None,
+            receiving: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.frame.clear();
+        self.byte = 0;
+        self.bits = 0;
+        self.half = None;
+        self.receiving = false;
+    }
+
+    fn bit(&mut self, one: bool) {
+        self.byte = (self.byte << 1) | one as u8;
+        self.bits += 1;
+        if self.bits == 8 {
+            // Longer frames are cut at MAX_FRAME_SIZE
+            self.frame.push(self.byte).ignore();
+            self.bits = 0;
+        }
+    }
+
+    // Returns the frame once the gap after it was seen
+    pub fn push(&mut self, p: Pulse) -> Option<RadioFrame> {
+        if !p.level && p.duration_us >= self.config.gap_threshold_us() {
+            // The last Manchester one ends in the gap
+            if self.half == Some(true) {
+                self.bit(true);
+            }
+            let frame = core::mem::take(&mut self.frame);
+            self.reset();
+            return (!frame.is_empty()).then_some(frame);
+        }
+
+        match self.config.code {
+            PulseCode::Pwm { short_us, long_us } => {
+                if !p.level {
+                    return None;
+                }
+                if p.duration_us < short_us / 2 || p.duration_us > long_us + long_us / 2 {
+                    self.reset();
+                    return None;
+                }
+                self.bit(p.duration_us > (short_us + long_us) / 2);
+            }
+            PulseCode::Manchester { half_us } => {
+                // Align on the first high half
+                if !self.receiving && !p.level {
+                    return None;
+                }
+                self.receiving = true;
+
+                let halves = (p.duration_us + half_us / 2) / half_us;
+                if !(1..=2).contains(&halves) {
+                    self.reset();
+                    return None;
+                }
+                for _ in 0..halves {
+                    match self.half.take() {
+                        None => self.half = Some(p.level),
+                        Some(first) if first != p.level => self.bit(first),
+                        // Two equal halves are not a bit
+                        Some(_) => {
+                            self.reset();
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::Vec;
+
+    use super::*;
+    use crate::frame::Framing;
+    use crate::packet::{PacketWithoutDC, RADIO_PACKET_SIZE};
+
+    const PWM: PulseConfig = PulseConfig {
+        code: PulseCode::Pwm {
+            short_us: 200,
+            long_us: 600,
+        },
+        gap_us: 4_000,
+    };
+
+    const MANCHESTER: PulseConfig = PulseConfig {
+        code: PulseCode::Manchester { half_us: 250 },
+        gap_us: 4_000,
+    };
+
+    #[test]
+    fn test_pwm_pulses() {
+        let pulses: Vec<Pulse, 32> = PulseEncoder::new([0b1010_0000], PWM).collect();
+        assert_eq!(
+            &pulses[..5],
+            &[
+                Pulse::high(600),
+                Pulse::low(200),
+                Pulse::high(200),
+                Pulse::low(600),
+                Pulse::high(600),
+            ]
+        );
+        assert_eq!(pulses.len(), 16);
+        // The pause of the last zero merges with the gap
+        assert_eq!(pulses[15], Pulse::low(4_600));
+
+        let total: u64 = pulses.iter().map(|p| p.duration_us as u64).sum();
+        assert_eq!(total, PWM.frame_duration_us(1));
+    }
+
+    #[test]
+    fn test_manchester_pulses() {
+        let pulses: Vec<Pulse, 32> = PulseEncoder::new([0b1001_1100], MANCHESTER).collect();
+        // Halves H L L H L H H L H L H L L H L H, then the gap
+        assert_eq!(
+            pulses.as_slice(),
+            &[
+                Pulse::high(250),
+                Pulse::low(500),
+                Pulse::high(250),
+                Pulse::low(250),
+                Pulse::high(500),
+                Pulse::low(250),
+                Pulse::high(250),
+                Pulse::low(250),
+                Pulse::high(250),
+                Pulse::low(500),
+                Pulse::high(250),
+                Pulse::low(250),
+                Pulse::high(250),
+                Pulse::low(4_000),
+            ]
+        );
+    }
+
+    fn round_trip(config: PulseConfig) {
+        let data: [u8; RADIO_PACKET_SIZE] =
+            core::array::from_fn(|i| [0x3c, 0x96, 0x5a, 0xe1][i % 4]);
+        let p = PacketWithoutDC::new(&data);
+        let frame = Framing::new(2, &[0x2d, 0xd4]).frame(&p);
+
+        let mut dec = PulseDecoder::new(config);
+        // Noise before the frame
+        assert_eq!(dec.push(Pulse::high(37)), None);
+        assert_eq!(dec.push(Pulse::low(10_000)), None);
+
+        let mut found = None;
+        for (n, mut pulse) in PulseEncoder::new(&frame, config).enumerate() {
+            // +-10% timing jitter
+            if pulse.duration_us < config.gap_us {
+                let jitter = pulse.duration_us / 10;
+                pulse.duration_us = match n % 3 {
+                    0 => pulse.duration_us - jitter,
+                    1 => pulse.duration_us + jitter,
+                    _ => pulse.duration_us,
+                };
+            }
+            if let Some(f) = dec.push(pulse) {
+                found = Some(f);
+            }
+        }
+        assert_eq!(found, Some(frame));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        round_trip(PWM);
+        round_trip(MANCHESTER);
+    }
+
+    fn bad_pulse(config: PulseConfig, bad: Pulse) {
+        let gap = Pulse::low(config.gap_us);
+        let mut dec = PulseDecoder::new(config);
+
+        // Two full bytes, without the gap
+        let head = [0xaa, 0x55];
+        let pulses: Vec<Pulse, 32> = PulseEncoder::new(head, config).collect();
+        for p in &pulses[..pulses.len() - 1] {
+            assert_eq!(dec.push(*p), None);
+        }
+        assert_eq!(
+            dec.clone().push(gap),
+            Some(RadioFrame::from_slice(&head).unwrap())
+        );
+
+        // The invalid pulse drops them
+        assert_eq!(dec.push(bad), None);
+        assert_eq!(dec.push(gap), None);
+
+        // The next frame is not affected
+        let frame = RadioFrame::from_slice(&[0xaa, 0x3c, 0x96]).unwrap();
+        let found = PulseEncoder::new(&frame, config).find_map(|p| dec.push(p));
+        assert_eq!(found, Some(frame));
+    }
+
+    #[test]
+    fn test_bad_pulse() {
+        // Three halves long
+        bad_pulse(MANCHESTER, Pulse::high(750));
+        // Longer than a long pulse
+        bad_pulse(PWM, Pulse::high(1_000));
+    }
+
+    #[test]
+    fn test_config() {
+        assert!(PWM.is_valid());
+        assert!(MANCHESTER.is_valid());
+        assert!(PulseConfig::default().is_valid());
+
+        let invalid = [
+            PulseCode::Manchester { half_us: 0 },
+            PulseCode::Pwm {
+                short_us: 0,
+                long_us: 600,
+            },
+            PulseCode::Pwm {
+                short_us: 600,
+                long_us: 600,
+            },
+        ];
+        for code in invalid {
+            assert!(!PulseConfig { code, ..PWM }.is_valid(), "{code:?}");
+        }
+
+        // The gap has to be longer than the longest pulse
+        let short_gap = PulseConfig {
+            gap_us: 500,
+            ..MANCHESTER
+        };
+        assert!(!short_gap.is_valid());
+        let huge = PulseConfig {
+            code: PulseCode::Manchester { half_us: u32::MAX },
+            ..MANCHESTER
+        };
+        assert!(!huge.is_valid());
+
+        // Long durations do not overflow the time on air
+        let slow = PulseConfig {
+            code: PulseCode::Pwm {
+                short_us: u32::MAX - 1,
+                long_us: u32::MAX,
+            },
+            gap_us: u32::MAX,
+        };
+        assert_eq!(
+            slow.frame_duration_us(64),
+            64 * 8 * (2 * u32::MAX as u64 - 1) + u32::MAX as u64
+        );
+        assert_eq!(slow.frame_duration_us(usize::MAX), u64::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid pulse config")]
+    fn test_invalid_decoder() {
+        PulseDecoder::new(PulseConfig {
+            code: PulseCode::Manchester { half_us: 0 },
+            ..MANCHESTER
+        });
+    }
+}
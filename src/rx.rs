@@ -1,13 +1,19 @@
 use crc::Algorithm;
 use crc::Digest;
 use crc::NoTable;
+use heapless::{Deque, Vec};
+use ignore_result::Ignore as _;
 use ufmt::derive::uDebug;
 
+use crate::line::{Dc6b8b, LineCode};
 use crate::message::Message;
 use crate::message::MessageVersion;
 use crate::packet::GolayDecoderResult;
 use crate::packet::PacketStatus;
-use crate::util::decode_extended_number;
+use crate::packet::PacketWithGolay;
+use crate::raw::RawReceiveData;
+use crate::stream::StreamDecoder;
+use crate::util::{decode_extended_number, decode_extended_number_with};
 
 const CRC8K_3: Algorithm<u8> = Algorithm {
     width: 8,
@@ -21,40 +27,375 @@ const CRC8K_3: Algorithm<u8> = Algorithm {
 };
 pub const LASO_CRC: crc::Crc<u8, NoTable> = crc::Crc::<u8, NoTable>::new(&CRC8K_3);
 
+// Maximum number of marginal codewords per packet considered by the repair pass
+const REPAIR_CODEWORDS: usize = 3;
+
+// How much the received data can be trusted, ordered from best to worst
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RxConfidence {
+    // Received without any bit errors
+    #[default]
+    Clean,
+    // Bit errors were corrected by the Golay code
+    Corrected,
+    // The CRC only matched after trying alternative codeword decodings,
+    // there is a small chance the CRC matched by accident
+    Repaired,
+}
+
 #[derive(Clone, Default)]
 pub struct RxMessage<const N: usize> {
     pub msg: Message<N>,
     pub rssi: u8,
     pub lna: u8,
     pub errors: u8,
+    pub confidence: RxConfidence,
+}
+
+// Storage for the header fields and payload of a message being received
+pub trait RxStorage {
+    fn set_version(&mut self, version: MessageVersion);
+    fn set_source_address(&mut self, source_address: u32);
+    fn set_packet_type(&mut self, packet_type: u32);
+    // The sender listens for a reply after the message
+    fn set_will_listen(&mut self, will_listen: bool);
+    fn is_empty(&self) -> bool;
+    // Number of payload bytes stored
+    fn len(&self) -> usize;
+    fn push(&mut self, b: u8) -> Result<(), u8>;
+    // Drop the payload bytes after len, used to undo a rejected packet
+    fn truncate(&mut self, len: usize);
+    // Drop the payload and header fields
+    fn clear(&mut self);
+}
+
+impl<const N: usize> RxStorage for Message<N> {
+    fn set_version(&mut self, version: MessageVersion) {
+        self.version = version;
+    }
+
+    fn set_source_address(&mut self, source_address: u32) {
+        self.source_address = source_address;
+    }
+
+    fn set_packet_type(&mut self, packet_type: u32) {
+        self.packet_type = Some(packet_type);
+    }
+
+    fn set_will_listen(&mut self, will_listen: bool) {
+        self.will_listen = will_listen;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn push(&mut self, b: u8) -> Result<(), u8> {
+        self.data.push(b)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+// Payload is decoded directly into a caller supplied buffer,
+// the size of the buffer is only known at runtime
+pub struct RxBuffer<'b> {
+    version: MessageVersion,
+    source_address: u32,
+    packet_type: Option<u32>,
+    will_listen: bool,
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> RxBuffer<'b> {
+    pub fn new(buffer: &'b mut [u8]) -> Self {
+        Self {
+            version: Default::default(),
+            source_address: 0,
+            packet_type: None,
+            will_listen: false,
+            buffer,
+            len: 0,
+        }
+    }
+}
+
+impl RxStorage for RxBuffer<'_> {
+    fn set_version(&mut self, version: MessageVersion) {
+        self.version = version;
+    }
+
+    fn set_source_address(&mut self, source_address: u32) {
+        self.source_address = source_address;
+    }
+
+    fn set_packet_type(&mut self, packet_type: u32) {
+        self.packet_type = Some(packet_type);
+    }
+
+    fn set_will_listen(&mut self, will_listen: bool) {
+        self.will_listen = will_listen;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, b: u8) -> Result<(), u8> {
+        let slot = self.buffer.get_mut(self.len).ok_or(b)?;
+        *slot = b;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    fn clear(&mut self) {
+        self.version = Default::default();
+        self.source_address = 0;
+        self.packet_type = None;
+        self.will_listen = false;
+        self.len = 0;
+    }
+}
+
+// Received message borrowing the payload from the RxBuffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RxMessageView<'b> {
+    pub version: MessageVersion,
+    pub source_address: u32,
+    pub packet_type: Option<u32>,
+    pub will_listen: bool,
+    pub payload: &'b [u8],
+    pub rssi: u8,
+    pub lna: u8,
+    pub errors: u8,
+    pub confidence: RxConfidence,
+}
+
+// Ring buffer storage, complete messages queue up in the caller supplied
+// buffer until the consumer takes them out, see RxRingDecoder::commit.
+// Up to Q messages are queued, the payload of a queued message may wrap
+// around the end of the buffer.
+pub struct RxRing<'b, const Q: usize> {
+    version: MessageVersion,
+    source_address: u32,
+    packet_type: Option<u32>,
+    will_listen: bool,
+    buffer: &'b mut [u8],
+    // Start of the message being received and its length
+    start: usize,
+    len: usize,
+    // Bytes held by the queued messages
+    used: usize,
+    queue: Deque<RxRingEntry, Q>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RxRingEntry {
+    version: MessageVersion,
+    source_address: u32,
+    packet_type: Option<u32>,
+    will_listen: bool,
+    rssi: u8,
+    lna: u8,
+    errors: u8,
+    confidence: RxConfidence,
+    start: usize,
+    len: usize,
+}
+
+// Queued message borrowing the payload from the RxRing, the payload
+// is split in two where it wraps around
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RxRingMessage<'b> {
+    pub version: MessageVersion,
+    pub source_address: u32,
+    pub packet_type: Option<u32>,
+    pub will_listen: bool,
+    pub payload: (&'b [u8], &'b [u8]),
+    pub rssi: u8,
+    pub lna: u8,
+    pub errors: u8,
+    pub confidence: RxConfidence,
+}
+
+impl<'b, const Q: usize> RxRing<'b, Q> {
+    pub fn new(buffer: &'b mut [u8]) -> Self {
+        Self {
+            version: Default::default(),
+            source_address: 0,
+            packet_type: None,
+            will_listen: false,
+            buffer,
+            start: 0,
+            len: 0,
+            used: 0,
+            queue: Deque::new(),
+        }
+    }
+
+    // Number of queued messages
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    // The oldest queued message
+    pub fn peek(&self) -> Option<RxRingMessage<'_>> {
+        let e = self.queue.front()?;
+        let end = e.start + e.len;
+        let payload = match end.checked_sub(self.buffer.len()) {
+            Some(wrapped) if wrapped > 0 => (&self.buffer[e.start..], &self.buffer[..wrapped]),
+            _ => (&self.buffer[e.start..end], &self.buffer[..0]),
+        };
+
+        Some(RxRingMessage {
+            version: e.version,
+            source_address: e.source_address,
+            packet_type: e.packet_type,
+            will_listen: e.will_listen,
+            payload,
+            rssi: e.rssi,
+            lna: e.lna,
+            errors: e.errors,
+            confidence: e.confidence,
+        })
+    }
+
+    // Drop the oldest queued message and free its bytes
+    pub fn pop(&mut self) -> bool {
+        match self.queue.pop_front() {
+            Some(e) => {
+                self.used -= e.len;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<const Q: usize> RxStorage for RxRing<'_, Q> {
+    fn set_version(&mut self, version: MessageVersion) {
+        self.version = version;
+    }
+
+    fn set_source_address(&mut self, source_address: u32) {
+        self.source_address = source_address;
+    }
+
+    fn set_packet_type(&mut self, packet_type: u32) {
+        self.packet_type = Some(packet_type);
+    }
+
+    fn set_will_listen(&mut self, will_listen: bool) {
+        self.will_listen = will_listen;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, b: u8) -> Result<(), u8> {
+        // Full when the message runs into the oldest queued one
+        if self.used + self.len >= self.buffer.len() {
+            return Err(b);
+        }
+        let idx = (self.start + self.len) % self.buffer.len();
+        self.buffer[idx] = b;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    // The queued messages stay
+    fn clear(&mut self) {
+        self.version = Default::default();
+        self.source_address = 0;
+        self.packet_type = None;
+        self.will_listen = false;
+        self.len = 0;
+    }
 }
 
 #[derive(Clone)]
-pub struct RxMessageDecoder<'a, const N: usize> {
-    pub msg: Message<N>,
+pub struct RxDecoder<'a, S: RxStorage> {
+    pub msg: S,
     pub rssi: u8,
     pub lna: u8,
     pub errors: u8,
+    pub confidence: RxConfidence,
 
     last_status: PacketStatus,
     crc8: Digest<'a, u8, NoTable>,
+
+    // Number of packets accepted so far
+    packets: usize,
+    // Set when the decoding failed
+    failed: Option<RxErrorKind>,
+    // Running sums of the radio metadata, used for averaging
+    rssi_sum: u32,
+    lna_sum: u32,
 }
 
-impl<'a, const N: usize> Default for RxMessageDecoder<'a, N> {
+// Decoder that copies the payload into a Message
+pub type RxMessageDecoder<'a, const N: usize> = RxDecoder<'a, Message<N>>;
+
+// Decoder that writes the payload into a caller supplied buffer
+pub type RxViewDecoder<'a, 'b> = RxDecoder<'a, RxBuffer<'b>>;
+
+// Decoder that queues the messages in a caller supplied ring buffer
+pub type RxRingDecoder<'a, 'b, const Q: usize> = RxDecoder<'a, RxRing<'b, Q>>;
+
+impl<'a, S: RxStorage + Default> Default for RxDecoder<'a, S> {
     fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl<'a, S: RxStorage> RxDecoder<'a, S> {
+    pub fn new(msg: S) -> Self {
         Self {
             crc8: LASO_CRC.digest(),
-            msg: Default::default(),
+            msg,
             rssi: Default::default(),
             lna: Default::default(),
             errors: Default::default(),
+            confidence: Default::default(),
             last_status: Default::default(),
+            packets: 0,
+            failed: None,
+            rssi_sum: 0,
+            lna_sum: 0,
         }
     }
 }
 
 #[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
-pub enum RxDecodeError {
+pub enum RxErrorKind {
     OutOfOrder,
     Unexpected,
     Invalid,
@@ -63,28 +404,274 @@ pub enum RxDecodeError {
     UnknownPacket,
     RawNeedsDecoding,
     InternalOnly,
+    // Raw capture ended in the middle of a packet
+    Truncated,
 }
 
-impl<'a, const N: usize> RxMessageDecoder<'a, N> {
+// Receive state machine of RxMessageDecoder
+//
+// Idle -> Receiving(n)  a packet of an unfinished message was appended
+// Idle -> Complete      a single packet message was appended
+// Receiving(n) -> Receiving(n + 1) / Complete
+// Idle / Receiving(n) -> Failed  append returned an error, the message is lost
+// Complete / Failed     append returns Unexpected, the state does not change
+// any -> Idle           reset() or a successful take_message()
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxState {
+    // Waiting for the first packet of a message
+    Idle,
+    // Number of packets received for an unfinished message
+    Receiving(usize),
+    // The last packet of a message was received
+    Complete,
+    // Decoding failed, the decoder needs a reset
+    Failed(RxErrorKind),
+}
+
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CrcMismatch {
+    // Computed from the received data
+    pub expected: u8,
+    // Received in the packet
+    pub received: u8,
+}
+
+// Decoding failure together with the state of the failing packet
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RxDecodeError {
+    pub kind: RxErrorKind,
+    // Index of the failing packet within the message
+    pub packet: usize,
+    // Packet status as seen by the decoder
+    pub status: PacketStatus,
+    // Only present for CrcFailed
+    pub crc: Option<CrcMismatch>,
+    // Golay corrections applied to the failing packet
+    pub errors: usize,
+    pub parity_errors: usize,
+}
+
+impl From<RxErrorKind> for RxDecodeError {
+    fn from(kind: RxErrorKind) -> Self {
+        Self {
+            kind,
+            packet: 0,
+            status: PacketStatus::Unknown,
+            crc: None,
+            errors: 0,
+            parity_errors: 0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Display for RxDecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:?} in packet {} (status {:?}",
+            self.kind, self.packet, self.status
+        )?;
+        if let Some(crc) = self.crc {
+            write!(
+                f,
+                ", expected CRC {:#04x}, received {:#04x}",
+                crc.expected, crc.received
+            )?;
+        }
+        write!(
+            f,
+            ", {} corrected bits, {} parity errors)",
+            self.errors, self.parity_errors
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::error::Error for RxDecodeError {}
+
+// Packet status and header fields decoded ahead of the payload,
+// the header fields are only present in the first packet of a message
+#[derive(defmt::Format, uDebug, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub status: PacketStatus,
+    pub packet_type: Option<u32>,
+    pub source_address: Option<u32>,
+}
+
+// Same as decode_extended_number, but runs the Golay decoder
+// only for the codewords carrying the number
+fn peek_extended_number(stream: &mut StreamDecoder, start: usize) -> Option<(u32, usize)> {
+    // 11 data bytes precede the status byte
+    decode_extended_number_with(11, start, |idx| stream.byte(idx))
+}
+
+impl<'a, S: RxStorage> RxDecoder<'a, S> {
     pub fn decode_status(&self, status: u8) -> PacketStatus {
         self.last_status.decode(status)
     }
 
-    pub fn append(&mut self, dec: &GolayDecoderResult) -> Result<PacketStatus, RxDecodeError> {
-        let p = &dec.data;
-        // Unexpected packet
+    // Is another packet expected at all?
+    fn check_expected(&self) -> Result<(), RxErrorKind> {
         #[cfg(feature = "legacy")]
         if let PacketStatus::Legacy(legacy) = self.last_status {
             if legacy.last {
-                return Err(RxDecodeError::Unexpected);
+                return Err(RxErrorKind::Unexpected);
             }
         }
 
         if let PacketStatus::V2(v2) = self.last_status {
             if v2.short {
-                return Err(RxDecodeError::Unexpected);
+                return Err(RxErrorKind::Unexpected);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Attach the context of the failing packet to the error
+    fn error(
+        &self,
+        kind: RxErrorKind,
+        status: PacketStatus,
+        dec: &GolayDecoderResult,
+    ) -> RxDecodeError {
+        RxDecodeError {
+            kind,
+            packet: self.packets,
+            status,
+            crc: None,
+            errors: dec.errors,
+            parity_errors: dec.parity_errors,
+        }
+    }
+
+    // Decode only the status byte and the header of the received packet.
+    //
+    // This allows the receiver to drop unwanted packets (by source address
+    // or type) before the rest of the packet goes through the Golay decoder.
+    // The already decoded codewords are kept in the stream decoder
+    // and are reused when the full packet is decoded later.
+    //
+    // The packet validity is not checked here, append() does that. The
+    // stream decoder has to match the framing of the link, see
+    // StreamDecoder::for_framing.
+    pub fn peek_header(&self, stream: &mut StreamDecoder) -> Result<PacketHeader, RxDecodeError> {
+        let error = |kind, status| RxDecodeError {
+            packet: self.packets,
+            status,
+            ..RxDecodeError::from(kind)
+        };
+
+        self.check_expected()
+            .map_err(|kind| error(kind, self.last_status))?;
+
+        let status = self.decode_status(
+            stream
+                .byte(11)
+                .ok_or(error(RxErrorKind::Truncated, self.last_status))?,
+        );
+        let mut header = PacketHeader {
+            status,
+            packet_type: None,
+            source_address: None,
+        };
+
+        let has_type = match status {
+            #[cfg(feature = "legacy")]
+            PacketStatus::Legacy(legacy) => {
+                if !legacy.first {
+                    return Ok(header);
+                }
+                true
+            }
+            PacketStatus::V2(v2) => !v2.naked,
+            PacketStatus::CRC8P(_) | PacketStatus::Data(_) => return Ok(header),
+            PacketStatus::Unknown => return Err(error(RxErrorKind::UnknownPacket, status)),
+            PacketStatus::Internal => return Err(error(RxErrorKind::InternalOnly, status)),
+            PacketStatus::Raw(_) => return Err(error(RxErrorKind::RawNeedsDecoding, status)),
+        };
+
+        let mut skip = 0;
+        if has_type {
+            let packet_type;
+            (packet_type, skip) =
+                peek_extended_number(stream, skip).ok_or(error(RxErrorKind::Truncated, status))?;
+            header.packet_type = Some(packet_type);
+        }
+        let (source_address, _) =
+            peek_extended_number(stream, skip).ok_or(error(RxErrorKind::Truncated, status))?;
+        header.source_address = Some(source_address);
+
+        Ok(header)
+    }
+
+    pub fn state(&self) -> RxState {
+        if let Some(kind) = self.failed {
+            RxState::Failed(kind)
+        } else if self.packets == 0 {
+            RxState::Idle
+        } else if self.last_status.finished() {
+            RxState::Complete
+        } else {
+            RxState::Receiving(self.packets)
+        }
+    }
+
+    // Drop all received data and wait for a new message
+    pub fn reset(&mut self) {
+        self.msg.clear();
+        self.rssi = 0;
+        self.lna = 0;
+        self.errors = 0;
+        self.confidence = Default::default();
+        self.last_status = Default::default();
+        self.crc8 = LASO_CRC.digest();
+        self.packets = 0;
+        self.failed = None;
+        self.rssi_sum = 0;
+        self.lna_sum = 0;
+    }
+
+    // The message is available in the Complete state. Multi packet V2 messages
+    // do not mark the last packet, so the message is also available
+    // in the Receiving state once the last packet passed the CRC check
+    // (or when the message is naked and has no CRC at all). The receiver
+    // decides when the message is over, usually by a timeout.
+    pub fn message_available(&self) -> bool {
+        match self.state() {
+            RxState::Complete => true,
+            RxState::Receiving(_) => {
+                matches!(
+                    self.last_status,
+                    PacketStatus::CRC8P(_) | PacketStatus::Data(_)
+                )
+            }
+            RxState::Idle | RxState::Failed(_) => false,
+        }
+    }
+
+    // Append a received packet to the message, see RxState for
+    // the state transitions
+    pub fn append(&mut self, dec: &GolayDecoderResult) -> Result<PacketStatus, RxDecodeError> {
+        if self.failed.is_some() {
+            return Err(self.error(RxErrorKind::Unexpected, dec.data.status, dec));
+        }
+
+        let res = self.append_packet(dec);
+        if let Err(err) = res {
+            if self.state() != RxState::Complete {
+                self.failed = Some(err.kind);
             }
         }
+        res
+    }
+
+    fn append_packet(&mut self, dec: &GolayDecoderResult) -> Result<PacketStatus, RxDecodeError> {
+        let p = &dec.data;
+        // Unexpected packet
+        self.check_expected()
+            .map_err(|kind| self.error(kind, p.status, dec))?;
 
         // Decode raw status
         let cur_status = if let PacketStatus::Raw(raw) = p.status {
@@ -95,7 +682,7 @@ impl<'a, const N: usize> RxMessageDecoder<'a, N> {
 
         // Check internal packet validity
         if !p.check_valid() {
-            return Err(RxDecodeError::Invalid);
+            return Err(self.error(RxErrorKind::Invalid, cur_status, dec));
         }
 
         // How many bytes were already consumed
@@ -111,45 +698,46 @@ impl<'a, const N: usize> RxMessageDecoder<'a, N> {
             #[cfg(feature = "legacy")]
             PacketStatus::Legacy(legacy) => {
                 // First packet flag when data already recorded?
-                if !self.msg.data.is_empty() && legacy.first {
-                    return Err(RxDecodeError::OutOfOrder);
+                if !self.msg.is_empty() && legacy.first {
+                    return Err(self.error(RxErrorKind::OutOfOrder, cur_status, dec));
                 }
 
                 // Checksum was tested as part of Packet.check_valid()
                 // above.
 
                 if legacy.first {
-                    let packet_type;
+                    let (packet_type, source_address);
                     (packet_type, skip) = decode_extended_number(dec.data.data.as_slice(), skip);
-                    self.msg.packet_type = Some(packet_type);
-                    (self.msg.source_address, skip) =
-                        decode_extended_number(dec.data.data.as_slice(), skip);
+                    self.msg.set_packet_type(packet_type);
+                    (source_address, skip) = decode_extended_number(dec.data.data.as_slice(), skip);
+                    self.msg.set_source_address(source_address);
                 }
 
-                self.msg.version = MessageVersion::LegacyLaso;
+                self.msg.set_version(MessageVersion::LegacyLaso);
             }
             PacketStatus::V2(v2) => {
-                let packet_type;
+                let (packet_type, source_address);
                 if !v2.naked {
                     (packet_type, skip) = decode_extended_number(dec.data.data.as_slice(), skip);
-                    self.msg.packet_type = Some(packet_type);
+                    self.msg.set_packet_type(packet_type);
                 }
-                (self.msg.source_address, skip) =
-                    decode_extended_number(dec.data.data.as_slice(), skip);
+                (source_address, skip) = decode_extended_number(dec.data.data.as_slice(), skip);
+                self.msg.set_source_address(source_address);
+                self.msg.set_will_listen(v2.listens);
 
                 if v2.naked {
                     if v2.short {
-                        self.msg.version = MessageVersion::NakedShort;
+                        self.msg.set_version(MessageVersion::NakedShort);
                     } else {
-                        self.msg.version = MessageVersion::Naked;
+                        self.msg.set_version(MessageVersion::Naked);
                     }
                 } else if v2.short {
-                    self.msg.version = MessageVersion::V2Short;
+                    self.msg.set_version(MessageVersion::V2Short);
                     // Subtract 1 from size, the last data byte contains CRC
                     // for the short packet
                     size -= 1;
                 } else {
-                    self.msg.version = MessageVersion::V2;
+                    self.msg.set_version(MessageVersion::V2);
                 }
 
                 if !v2.naked {
@@ -166,8 +754,15 @@ impl<'a, const N: usize> RxMessageDecoder<'a, N> {
                         // Test checksum without modifying the digest
                         // this allows using the same running digest
                         // for followup packets
-                        if crc != self.crc8.clone().finalize() {
-                            return Err(RxDecodeError::CrcFailed);
+                        let expected = self.crc8.clone().finalize();
+                        if crc != expected {
+                            return Err(RxDecodeError {
+                                crc: Some(CrcMismatch {
+                                    expected,
+                                    received: crc,
+                                }),
+                                ..self.error(RxErrorKind::CrcFailed, cur_status, dec)
+                            });
                         }
                     }
                 }
@@ -179,13 +774,26 @@ impl<'a, const N: usize> RxMessageDecoder<'a, N> {
                 // Test checksum without modifying the digest
                 // this allows using the same running digest
                 // for followup packets
-                if crc != self.crc8.clone().finalize() {
-                    return Err(RxDecodeError::CrcFailed);
+                let expected = self.crc8.clone().finalize();
+                if crc != expected {
+                    return Err(RxDecodeError {
+                        crc: Some(CrcMismatch {
+                            expected,
+                            received: crc,
+                        }),
+                        ..self.error(RxErrorKind::CrcFailed, cur_status, dec)
+                    });
                 }
             }
-            PacketStatus::Unknown => return Err(RxDecodeError::UnknownPacket),
-            PacketStatus::Internal => return Err(RxDecodeError::InternalOnly),
-            PacketStatus::Raw(_) => return Err(RxDecodeError::RawNeedsDecoding),
+            PacketStatus::Unknown => {
+                return Err(self.error(RxErrorKind::UnknownPacket, cur_status, dec))
+            }
+            PacketStatus::Internal => {
+                return Err(self.error(RxErrorKind::InternalOnly, cur_status, dec))
+            }
+            PacketStatus::Raw(_) => {
+                return Err(self.error(RxErrorKind::RawNeedsDecoding, cur_status, dec))
+            }
             PacketStatus::Data(_) => {
                 // Naked packet, ignore here and append data lower
             }
@@ -194,17 +802,353 @@ impl<'a, const N: usize> RxMessageDecoder<'a, N> {
         self.last_status = cur_status;
         self.errors = self.errors.saturating_add(dec.errors as u8);
         self.errors = self.errors.saturating_add(dec.parity_errors as u8);
+        if dec.errors > 0 || dec.parity_errors > 0 {
+            self.confidence = self.confidence.max(RxConfidence::Corrected);
+        }
 
+        let full = self.error(RxErrorKind::Full, cur_status, dec);
         for b in &p.data[skip..size] {
-            self.msg.data.push(*b).map_err(|_| RxDecodeError::Full)?;
+            self.msg.push(*b).map_err(|_| full)?;
         }
 
         if let PacketStatus::Data(b) = self.last_status {
-            self.msg.data.push(b).map_err(|_| RxDecodeError::Full)?;
+            self.msg.push(b).map_err(|_| full)?;
+        }
+
+        self.packets += 1;
+
+        Ok(self.last_status)
+    }
+
+    // The message ended before the last packet
+    pub(crate) fn truncated(&self) -> RxDecodeError {
+        RxDecodeError {
+            packet: self.packets,
+            status: self.last_status,
+            ..RxErrorKind::Truncated.into()
+        }
+    }
+
+    // Split a raw 6b/8b radio capture into packets and append them one by
+    // one. Packets after the last one of a message are ignored.
+    //
+    // The rssi and lna values of the capture are recorded for every
+    // appended packet and the message reports the average.
+    pub fn append_raw<const M: usize>(
+        &mut self,
+        raw: &RawReceiveData<M>,
+    ) -> Result<PacketStatus, RxDecodeError> {
+        self.append_coded(&Dc6b8b, raw)
+    }
+
+    // Same as append_raw, for links with a different line code. Pass
+    // the Framing for links with a different packet layout.
+    pub fn append_coded<const M: usize>(
+        &mut self,
+        code: &impl LineCode,
+        raw: &RawReceiveData<M>,
+    ) -> Result<PacketStatus, RxDecodeError> {
+        let size = code.size();
+        for frame in raw.packet.chunks(size) {
+            if self.state() == RxState::Complete {
+                break;
+            }
+
+            if frame.len() < size {
+                return Err(self.truncated());
+            }
+
+            self.append_received(&code.decode_packet(frame), raw.rssi, raw.lna)?;
         }
 
         Ok(self.last_status)
     }
+
+    // Same as append, records the rssi and lna the packet was received with
+    pub fn append_received(
+        &mut self,
+        dec: &GolayDecoderResult,
+        rssi: u8,
+        lna: u8,
+    ) -> Result<PacketStatus, RxDecodeError> {
+        let status = self.append(dec)?;
+
+        self.rssi_sum += rssi as u32;
+        self.lna_sum += lna as u32;
+        self.rssi = (self.rssi_sum / self.packets as u32) as u8;
+        self.lna = (self.lna_sum / self.packets as u32) as u8;
+        Ok(status)
+    }
+}
+
+// Decoder state without the stored payload, see append_with_repair
+struct Checkpoint<'a> {
+    rssi: u8,
+    lna: u8,
+    errors: u8,
+    confidence: RxConfidence,
+    last_status: PacketStatus,
+    crc8: Digest<'a, u8, NoTable>,
+    packets: usize,
+    failed: Option<RxErrorKind>,
+    rssi_sum: u32,
+    lna_sum: u32,
+    len: usize,
+}
+
+impl<'a, S: RxStorage> RxDecoder<'a, S> {
+    fn checkpoint(&self) -> Checkpoint<'a> {
+        Checkpoint {
+            rssi: self.rssi,
+            lna: self.lna,
+            errors: self.errors,
+            confidence: self.confidence,
+            last_status: self.last_status,
+            crc8: self.crc8.clone(),
+            packets: self.packets,
+            failed: self.failed,
+            rssi_sum: self.rssi_sum,
+            lna_sum: self.lna_sum,
+            len: self.msg.len(),
+        }
+    }
+
+    // Undo the packets appended after the checkpoint. Header fields are
+    // only written by the first packet, so dropping the payload bytes is
+    // enough unless the message was empty.
+    fn rollback(&mut self, c: &Checkpoint<'a>) {
+        self.rssi = c.rssi;
+        self.lna = c.lna;
+        self.errors = c.errors;
+        self.confidence = c.confidence;
+        self.last_status = c.last_status;
+        self.crc8 = c.crc8.clone();
+        self.packets = c.packets;
+        self.failed = c.failed;
+        self.rssi_sum = c.rssi_sum;
+        self.lna_sum = c.lna_sum;
+        if c.packets == 0 {
+            self.msg.clear();
+        } else {
+            self.msg.truncate(c.len);
+        }
+    }
+
+    // Same as append, but when the CRC check fails, the least confident
+    // codewords (parity failures and 3 bit corrections) are replaced
+    // by their alternative decodings until the CRC matches.
+    //
+    // At most `max_attempts` alternative packets are tried, each attempt
+    // costs one CRC update. Finding the alternatives costs 24 Golay decoder
+    // runs per marginal codeword. Keep the budget small, every attempt
+    // has a 1 in 256 chance of matching the CRC8 by accident.
+    //
+    // A repaired message reports the Repaired confidence level. The decoder
+    // moves to the Failed state when the repair fails.
+    pub fn append_with_repair(
+        &mut self,
+        dec: &GolayDecoderResult,
+        max_attempts: usize,
+    ) -> Result<PacketStatus, RxDecodeError> {
+        let start = self.checkpoint();
+        let crc_error = match self.append(dec) {
+            Err(err) if err.kind == RxErrorKind::CrcFailed => err,
+            res => return res,
+        };
+
+        // Find the marginal codewords, the least confident first
+        let mut words = [0_u16; 8];
+        let mut corrected = [0_usize; 8];
+        let mut marginal: Vec<(usize, usize), 8> = Vec::new();
+        for (idx, cw) in dec.codewords.iter().enumerate() {
+            let (word, err, parity) = PacketWithGolay::undo_golay(*cw);
+            words[idx] = word;
+            corrected[idx] = err;
+            if !parity {
+                marginal.push((usize::MAX, idx)).ok();
+            } else if err >= 3 {
+                marginal.push((err, idx)).ok();
+            }
+        }
+        marginal.sort_unstable_by_key(|(badness, _)| core::cmp::Reverse(*badness));
+        marginal.truncate(REPAIR_CODEWORDS);
+
+        let mut alternatives: [Vec<u16, 24>; REPAIR_CODEWORDS] = Default::default();
+        for ((_, idx), alt) in marginal.iter().zip(alternatives.iter_mut()) {
+            PacketWithGolay::alternatives(dec.codewords[*idx], alt);
+        }
+
+        let unrepaired = GolayDecoderResult::from_words(&words, &dec.codewords, 0, 0);
+
+        let mut attempts = 0;
+        let mut try_words = |candidate: &[u16; 8]| {
+            if attempts >= max_attempts {
+                self.rollback(&start);
+                self.failed = Some(crc_error.kind);
+                return Some(Err(crc_error));
+            }
+            attempts += 1;
+
+            // Bits flipped by the replaced codewords instead of the
+            // corrections of the original decoding
+            let mut errors = dec.errors;
+            for (idx, (word, cw)) in candidate.iter().zip(&dec.codewords).enumerate() {
+                if *word != words[idx] {
+                    let flipped = (PacketWithGolay::apply_golay(*word) ^ cw).count_ones();
+                    errors = errors.saturating_sub(corrected[idx]) + flipped as usize;
+                }
+            }
+
+            let mut repaired = GolayDecoderResult::from_words(
+                candidate,
+                &dec.codewords,
+                errors,
+                dec.parity_errors,
+            );
+            // Restore the status the caller already resolved, unless
+            // the repair changed the status byte
+            if !matches!(dec.data.status, PacketStatus::Raw(_))
+                && repaired.data.status == unrepaired.data.status
+            {
+                repaired.data.status = dec.data.status;
+            }
+
+            self.rollback(&start);
+            self.append(&repaired).ok().map(|status| {
+                self.confidence = RxConfidence::Repaired;
+                Ok(status)
+            })
+        };
+
+        // Single codeword replacements first, then pairs
+        for (a, (_, idx_a)) in marginal.iter().enumerate() {
+            for alt_a in &alternatives[a] {
+                let mut candidate = words;
+                candidate[*idx_a] = *alt_a;
+                if let Some(res) = try_words(&candidate) {
+                    return res;
+                }
+            }
+        }
+
+        for (a, (_, idx_a)) in marginal.iter().enumerate() {
+            for (b, (_, idx_b)) in marginal.iter().enumerate().skip(a + 1) {
+                for alt_a in &alternatives[a] {
+                    for alt_b in &alternatives[b] {
+                        let mut candidate = words;
+                        candidate[*idx_a] = *alt_a;
+                        candidate[*idx_b] = *alt_b;
+                        if let Some(res) = try_words(&candidate) {
+                            return res;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.rollback(&start);
+        self.failed = Some(crc_error.kind);
+        Err(crc_error)
+    }
+}
+
+impl<'a, const N: usize> RxMessageDecoder<'a, N> {
+    // Return the received message and reset the decoder,
+    // see message_available()
+    pub fn take_message(&mut self) -> Option<RxMessage<N>> {
+        if !self.message_available() {
+            return None;
+        }
+
+        Some(core::mem::take(self).into())
+    }
+}
+
+impl<'a, 'b> RxViewDecoder<'a, 'b> {
+    pub fn with_buffer(buffer: &'b mut [u8]) -> Self {
+        Self::new(RxBuffer::new(buffer))
+    }
+
+    // Borrow the received message, see message_available().
+    // Call reset() before receiving the next message.
+    pub fn view(&self) -> Option<RxMessageView<'_>> {
+        if !self.message_available() {
+            return None;
+        }
+
+        Some(RxMessageView {
+            version: self.msg.version,
+            source_address: self.msg.source_address,
+            packet_type: self.msg.packet_type,
+            will_listen: self.msg.will_listen,
+            payload: &self.msg.buffer[..self.msg.len],
+            rssi: self.rssi,
+            lna: self.lna,
+            errors: self.errors,
+            confidence: self.confidence,
+        })
+    }
+}
+
+impl<'a, 'b, const Q: usize> RxRingDecoder<'a, 'b, Q> {
+    pub fn with_ring(buffer: &'b mut [u8]) -> Self {
+        Self::new(RxRing::new(buffer))
+    }
+
+    // Queue the received message in the ring and reset the decoder for
+    // the next one, see message_available(). Returns false when there is
+    // no message or the queue is full.
+    pub fn commit(&mut self) -> bool {
+        if !self.message_available() || self.msg.queue.is_full() {
+            return false;
+        }
+
+        let ring = &mut self.msg;
+        let entry = RxRingEntry {
+            version: ring.version,
+            source_address: ring.source_address,
+            packet_type: ring.packet_type,
+            will_listen: ring.will_listen,
+            rssi: self.rssi,
+            lna: self.lna,
+            errors: self.errors,
+            confidence: self.confidence,
+            start: ring.start,
+            len: ring.len,
+        };
+        // Cannot fail, checked above
+        ring.queue.push_back(entry).ignore();
+        ring.used += ring.len;
+        ring.start = (ring.start + ring.len)
+            .checked_rem(ring.buffer.len())
+            .unwrap_or(0);
+        self.reset();
+        true
+    }
+}
+
+// Decode a message from a sequence of raw 6b/8b radio captures
+pub fn decode_raw<'r, const N: usize, const M: usize>(
+    captures: impl IntoIterator<Item = &'r RawReceiveData<M>>,
+) -> Result<RxMessage<N>, RxDecodeError> {
+    decode_coded(&Dc6b8b, captures)
+}
+
+// Same as decode_raw for other line codes, or for the Framing of a link
+pub fn decode_coded<'r, const N: usize, const M: usize>(
+    code: &impl LineCode,
+    captures: impl IntoIterator<Item = &'r RawReceiveData<M>>,
+) -> Result<RxMessage<N>, RxDecodeError> {
+    let mut rx: RxMessageDecoder<N> = RxMessageDecoder::default();
+    for raw in captures {
+        rx.append_coded(code, raw)?;
+    }
+
+    // Captures that end before the last packet are an unchecked message
+    match rx.take_message() {
+        Some(msg) => Ok(msg),
+        None => Err(rx.truncated()),
+    }
 }
 
 impl<const N: usize> From<Message<N>> for RxMessage<N> {
@@ -233,6 +1177,210 @@ impl<'a, const N: usize> From<RxMessageDecoder<'a, N>> for RxMessage<N> {
             rssi: msg.rssi,
             lna: msg.lna,
             errors: msg.errors,
+            confidence: msg.confidence,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tx::MessageSender;
+
+    fn test_message() -> Message<22> {
+        let mut msg: Message<22> = Message {
+            source_address: 0x55,
+            packet_type: Some(0x2),
+            version: MessageVersion::V2,
+            ..Default::default()
+        };
+        for b in 0..20 {
+            msg.add(b as u8);
         }
+        msg
+    }
+
+    // Simulate reception of codewords with the `mask` bits flipped
+    fn corrupt(dec: &GolayDecoderResult, codeword: usize, mask: u32) -> GolayDecoderResult {
+        let mut codewords = dec.codewords;
+        codewords[codeword] ^= mask;
+
+        let mut words = [0_u16; 8];
+        let mut errors = 0;
+        let mut parity_errors = 0;
+        for (word, cw) in words.iter_mut().zip(codewords.iter()) {
+            let (w, err, parity) = PacketWithGolay::undo_golay(*cw);
+            *word = w;
+            errors += err;
+            if !parity {
+                parity_errors += 1;
+            }
+        }
+        GolayDecoderResult::from_words(&words, &codewords, errors, parity_errors)
+    }
+
+    fn received(msg: &Message<22>) -> [GolayDecoderResult; 2] {
+        let mut sender = MessageSender::new(msg.clone());
+        let first = sender.packet().encode_for_transmit().decode();
+        let second = sender.packet().encode_for_transmit().decode();
+        assert!(!sender.data_to_send());
+        [first, second]
+    }
+
+    #[test]
+    fn test_repair() {
+        let msg = test_message();
+        let [first, second] = received(&msg);
+
+        // Four bit errors are beyond the Golay correction capability
+        let second = corrupt(&second, 2, 0b1111 << 4);
+
+        let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+        rx.append(&first).unwrap();
+
+        let mut plain = rx.clone();
+        let err = plain.append(&second).unwrap_err();
+        assert_eq!(err.kind, RxErrorKind::CrcFailed);
+        assert_eq!(err.packet, 1);
+        let crc = err.crc.expect("CRC mismatch details missing");
+        assert_ne!(crc.expected, crc.received);
+        assert!(err.errors > 0);
+
+        // Too small budget leaves the decoder untouched
+        let mut limited = rx.clone();
+        assert_eq!(limited.append_with_repair(&second, 0), Err(err));
+        assert_eq!(limited.packets, 1);
+        assert_eq!(limited.confidence, RxConfidence::Clean);
+        assert_eq!(limited.state(), RxState::Failed(RxErrorKind::CrcFailed));
+
+        assert!(rx.append_with_repair(&second, 64).is_ok());
+        assert_eq!(rx.confidence, RxConfidence::Repaired);
+        assert_eq!(rx.msg, msg);
+        // The repaired codeword adds its flipped bits
+        assert!(rx.errors as usize >= first.errors + 4);
+    }
+
+    #[test]
+    fn test_repair_resolved_status() {
+        let msg = test_message();
+        let [first, second] = received(&msg);
+
+        // The caller already resolved the status, the repair leaves
+        // the status byte alone and keeps it
+        let mut second = corrupt(&second, 2, 0b1111 << 4);
+        let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+        rx.append(&first).unwrap();
+        let PacketStatus::Raw(raw) = second.data.status else {
+            panic!("Expected a raw status");
+        };
+        second.data.status = rx.decode_status(raw);
+
+        assert_eq!(rx.append_with_repair(&second, 64), Ok(second.data.status));
+        assert_eq!(rx.confidence, RxConfidence::Repaired);
+        assert_eq!(rx.msg, msg);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_error_display() {
+        use core::fmt::Write as _;
+
+        let err = RxDecodeError {
+            packet: 1,
+            status: PacketStatus::CRC8P(0x34),
+            crc: Some(CrcMismatch {
+                expected: 0x12,
+                received: 0x34,
+            }),
+            errors: 3,
+            ..RxErrorKind::CrcFailed.into()
+        };
+
+        let mut out: heapless::String<128> = heapless::String::new();
+        write!(out, "{err}").unwrap();
+        assert_eq!(
+            out,
+            "CrcFailed in packet 1 (status CRC8P(52), expected CRC 0x12, received 0x34, 3 corrected bits, 0 parity errors)"
+        );
+    }
+
+    #[test]
+    fn test_state_machine() {
+        let msg = test_message();
+        let [first, second] = received(&msg);
+
+        let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+        assert_eq!(rx.state(), RxState::Idle);
+        assert!(rx.take_message().is_none());
+
+        // The first packet of a long message has no CRC
+        rx.append(&first).unwrap();
+        assert_eq!(rx.state(), RxState::Receiving(1));
+        assert!(rx.take_message().is_none());
+
+        rx.append(&second).unwrap();
+        assert_eq!(rx.state(), RxState::Receiving(2));
+        let rx_msg = rx.take_message().expect("Message not available");
+        assert_eq!(rx_msg.msg, msg);
+        assert_eq!(rx.state(), RxState::Idle);
+
+        // Failure needs a reset
+        let corrupted = corrupt(&second, 2, 0b1111 << 4);
+        rx.append(&first).unwrap();
+        assert!(rx.append(&corrupted).is_err());
+        assert_eq!(rx.state(), RxState::Failed(RxErrorKind::CrcFailed));
+        assert_eq!(
+            rx.append(&second).unwrap_err().kind,
+            RxErrorKind::Unexpected
+        );
+        assert!(rx.take_message().is_none());
+
+        rx.reset();
+        assert_eq!(rx.state(), RxState::Idle);
+        rx.append(&first).unwrap();
+        rx.append(&second).unwrap();
+        assert_eq!(rx.take_message().unwrap().msg, msg);
+    }
+
+    #[test]
+    fn test_state_complete() {
+        let msg: Message<22> = Message {
+            source_address: 0x55,
+            packet_type: Some(0x2),
+            version: MessageVersion::V2Short,
+            ..Default::default()
+        };
+        let mut sender = MessageSender::new(msg);
+        let packet = sender.packet().encode_for_transmit().decode();
+
+        let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+        rx.append(&packet).unwrap();
+        assert_eq!(rx.state(), RxState::Complete);
+
+        // The next packet is not part of the message
+        assert_eq!(
+            rx.append(&packet).unwrap_err().kind,
+            RxErrorKind::Unexpected
+        );
+        assert_eq!(rx.state(), RxState::Complete);
+
+        let rx_msg = rx.take_message().expect("Message not available");
+        assert_eq!(rx_msg.msg.version, MessageVersion::V2Short);
+        assert_eq!(rx.state(), RxState::Idle);
+    }
+
+    #[test]
+    fn test_repair_not_needed() {
+        let msg = test_message();
+        let [first, second] = received(&msg);
+        let second = corrupt(&second, 5, 0b101);
+
+        let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+        assert!(rx.append_with_repair(&first, 64).is_ok());
+        assert_eq!(rx.confidence, RxConfidence::Clean);
+        assert!(rx.append_with_repair(&second, 64).is_ok());
+        assert_eq!(rx.confidence, RxConfidence::Corrected);
+        assert!(rx.errors >= 2);
+        assert_eq!(rx.msg, msg);
     }
 }
@@ -0,0 +1,309 @@
+// Deterministic radio channel simulator for tests and benchmarks.
+//
+// Radio packets go through the same kinds of damage a real receiver sees:
+// independent bit flips (the AWGN equivalent for a given bit error rate),
+// Gilbert-Elliott burst errors, bit slips caused by clock recovery, packets
+// truncated when the receiver loses the signal and packets dropped as a
+// whole when the sync word is missed.
+//
+// Everything is driven by a seeded generator, the same seed always
+// produces the same damage.
+
+use heapless::Vec;
+
+use crate::packet::{PacketWithoutDC, RADIO_PACKET_SIZE};
+
+// Bytes received for a single packet, shorter when truncated
+pub type SimPacket = Vec<u8, RADIO_PACKET_SIZE>;
+
+// splitmix64, good enough for noise and fully reproducible
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+
+    // Uniform in [0, n)
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+// Bit error rate of non-coherent FSK for the given Eb/N0
+pub fn fsk_ber(ebn0_db: f64) -> f64 {
+    let ebn0 = 10f64.powf(ebn0_db / 10.0);
+    0.5 * (-ebn0 / 2.0).exp()
+}
+
+// Two state burst error model, the channel moves between a good
+// and a bad state after every bit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GilbertElliott {
+    pub p_good_to_bad: f64,
+    pub p_bad_to_good: f64,
+    pub ber_good: f64,
+    pub ber_bad: f64,
+}
+
+impl GilbertElliott {
+    // Bursts of mean_burst bits starting with the given probability per
+    // bit, half of the bits in a burst are flipped
+    pub fn bursts(burst_probability: f64, mean_burst: f64) -> Self {
+        Self {
+            p_good_to_bad: burst_probability,
+            p_bad_to_good: 1.0 / mean_burst.max(1.0),
+            ber_good: 0.0,
+            ber_bad: 0.5,
+        }
+    }
+
+    // Bursts of mean_burst bits with the given long term bit error rate
+    pub fn with_ber(ber: f64, mean_burst: f64) -> Self {
+        let mut ge = Self::bursts(0.0, mean_burst);
+        // Fraction of bits in the bad state, see average_ber
+        let bad = (ber / ge.ber_bad).min(0.99);
+        ge.p_good_to_bad = ge.p_bad_to_good * bad / (1.0 - bad);
+        ge
+    }
+
+    // Long term bit error rate of the model
+    pub fn average_ber(&self) -> f64 {
+        let bad = self.p_good_to_bad / (self.p_good_to_bad + self.p_bad_to_good);
+        bad * self.ber_bad + (1.0 - bad) * self.ber_good
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelConfig {
+    // Probability of an independent bit flip
+    pub ber: f64,
+    pub bursts: Option<GilbertElliott>,
+    // Per packet probabilities
+    pub slip: f64,
+    pub truncate: f64,
+    pub drop: f64,
+}
+
+impl ChannelConfig {
+    pub fn ber(ber: f64) -> Self {
+        Self {
+            ber,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub packets: usize,
+    pub bit_errors: usize,
+    pub slipped: usize,
+    pub truncated: usize,
+    pub dropped: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub config: ChannelConfig,
+    pub stats: ChannelStats,
+    rng: SimRng,
+    // Gilbert-Elliott state, kept between packets
+    bad: bool,
+}
+
+impl Channel {
+    pub fn new(config: ChannelConfig, seed: u64) -> Self {
+        Self {
+            config,
+            stats: Default::default(),
+            rng: SimRng::new(seed),
+            bad: false,
+        }
+    }
+
+    // Send a radio packet through the channel, None when it was dropped
+    pub fn transmit(&mut self, p: &PacketWithoutDC) -> Option<SimPacket> {
+        self.stats.packets += 1;
+        if self.rng.chance(self.config.drop) {
+            self.stats.dropped += 1;
+            return None;
+        }
+
+        let mut data = p.data();
+        self.stats.bit_errors += self.flip_bits(&mut data);
+
+        if self.rng.chance(self.config.slip) {
+            self.stats.slipped += 1;
+            self.slip(&mut data);
+        }
+
+        let mut len = data.len();
+        if self.rng.chance(self.config.truncate) {
+            self.stats.truncated += 1;
+            len = self.rng.below(data.len());
+        }
+
+        Vec::from_slice(&data[..len]).ok()
+    }
+
+    // Apply the independent and burst bit errors, returns the number of flipped bits
+    pub fn flip_bits(&mut self, data: &mut [u8]) -> usize {
+        let mut flipped = 0;
+        for byte in data.iter_mut() {
+            for bit in 0..8 {
+                let ber = match self.config.bursts {
+                    Some(ge) => {
+                        let flip = if self.bad {
+                            self.rng.chance(ge.p_bad_to_good)
+                        } else {
+                            self.rng.chance(ge.p_good_to_bad)
+                        };
+                        self.bad ^= flip;
+                        let burst = if self.bad { ge.ber_bad } else { ge.ber_good };
+                        self.config.ber + burst
+                    }
+                    None => self.config.ber,
+                };
+
+                if self.rng.chance(ber) {
+                    *byte ^= 0x80 >> bit;
+                    flipped += 1;
+                }
+            }
+        }
+        flipped
+    }
+
+    // Lose or duplicate a single bit, everything after it moves
+    // by one bit position. The bits are shifted MSb first.
+    pub fn slip(&mut self, data: &mut [u8]) {
+        let bits = data.len() * 8;
+        if bits == 0 {
+            return;
+        }
+
+        let at = self.rng.below(bits);
+        let get = |data: &[u8], i: usize| (data[i / 8] >> (7 - i % 8)) & 1;
+        let set = |data: &mut [u8], i: usize, v: u8| {
+            data[i / 8] = (data[i / 8] & !(0x80 >> (i % 8))) | (v << (7 - i % 8));
+        };
+
+        if self.rng.chance(0.5) {
+            // Bit lost, the missing tail is noise
+            for i in at..bits - 1 {
+                let v = get(data, i + 1);
+                set(data, i, v);
+            }
+            let v = (self.rng.next_u64() & 1) as u8;
+            set(data, bits - 1, v);
+        } else {
+            // Bit sampled twice
+            for i in (at + 1..bits).rev() {
+                let v = get(data, i - 1);
+                set(data, i, v);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_packet() -> PacketWithoutDC {
+        PacketWithoutDC::new(&[0x5a; RADIO_PACKET_SIZE])
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let config = ChannelConfig {
+            ber: 0.01,
+            bursts: Some(GilbertElliott::bursts(0.01, 8.0)),
+            slip: 0.2,
+            truncate: 0.2,
+            drop: 0.2,
+        };
+        let mut a = Channel::new(config, 7);
+        let mut b = Channel::new(config, 7);
+        for _ in 0..100 {
+            assert_eq!(a.transmit(&test_packet()), b.transmit(&test_packet()));
+        }
+        assert_eq!(a.stats, b.stats);
+        assert!(a.stats.dropped > 0 && a.stats.truncated > 0 && a.stats.slipped > 0);
+    }
+
+    #[test]
+    fn test_ber() {
+        let mut ch = Channel::new(ChannelConfig::ber(0.01), 1);
+        for _ in 0..1000 {
+            ch.transmit(&test_packet());
+        }
+        // 256000 bits, 2560 expected errors
+        assert!((2300..2800).contains(&ch.stats.bit_errors));
+
+        let mut clean = Channel::new(ChannelConfig::default(), 1);
+        assert_eq!(
+            clean.transmit(&test_packet()).unwrap(),
+            test_packet().data()
+        );
+    }
+
+    #[test]
+    fn test_bursts() {
+        let ge = GilbertElliott::bursts(0.001, 16.0);
+        let mut ch = Channel::new(
+            ChannelConfig {
+                bursts: Some(ge),
+                ..Default::default()
+            },
+            3,
+        );
+        for _ in 0..1000 {
+            ch.transmit(&test_packet());
+        }
+        let ber = ch.stats.bit_errors as f64 / 256_000.0;
+        assert!((ber - ge.average_ber()).abs() < ge.average_ber() / 2.0);
+
+        let ge = GilbertElliott::with_ber(1e-3, 8.0);
+        assert!((ge.average_ber() - 1e-3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_slip() {
+        let mut ch = Channel::new(ChannelConfig::default(), 5);
+        for _ in 0..20 {
+            // With alternating bits every bit after the slip is inverted
+            let mut data = [0xaa_u8; 4];
+            ch.slip(&mut data);
+            let diff = u32::from_be_bytes(data) ^ 0xaaaa_aaaa;
+            // The last bit might be noise
+            let tail = diff | 1;
+            assert_eq!(tail.count_ones(), 32 - tail.leading_zeros());
+        }
+    }
+
+    #[test]
+    fn test_fsk_ber() {
+        assert!((fsk_ber(0.0) - 0.5 * (-0.5f64).exp()).abs() < 1e-12);
+        assert!(fsk_ber(12.0) < 1e-3);
+    }
+}
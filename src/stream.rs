@@ -0,0 +1,368 @@
+// Incremental packet decoder for radios that hand over the received
+// data a few bytes at a time (FIFO level interrupts).
+//
+// Each received byte is line decoded (for 6b/8b stripped of the DC
+// balancing bits) and the resulting de-interleaved bits are distributed
+// to the eight Golay codewords immediately. The interleaver puts bit N
+// of every codeword into the same byte, so all codewords are complete
+// only after the last byte arrives. The Golay decoding can then be done
+// one codeword per `step` call to keep the time spent in a single
+// interrupt short. Packets with the Sequential layout, see interleave,
+// need a decoder made with for_framing.
+//
+// Single codewords can also be decoded out of order using `byte`. This is
+// used to look at the packet status and header before spending time
+// on the rest of the packet.
+
+use crate::dc::strip;
+use crate::frame::Framing;
+use crate::interleave::PacketInterleave;
+use crate::line::{LineCoding, Manchester, Pn9};
+use crate::packet::{GolayDecoderResult, PacketWithGolay, PacketWithoutDC};
+
+// Number of Golay codewords in a single packet
+pub const CODEWORDS: usize = 8;
+
+// Number of de-interleaved bytes in a single packet (24 bits per codeword)
+const INTERLEAVED_SIZE: usize = 24;
+
+#[derive(Clone, Debug, Default)]
+pub struct StreamDecoder {
+    line_code: LineCoding,
+    packet_interleave: PacketInterleave,
+
+    // Number of raw radio bytes consumed
+    received: usize,
+
+    // 6b -> 8b reassembly buffer, the first Manchester nibble
+    buff: u16,
+    buff_cnt: u8,
+
+    // Number of de-interleaved bytes distributed to the codewords
+    interleaved: usize,
+    codewords: [u32; CODEWORDS],
+
+    // Golay decoding progress, one bit per decoded codeword
+    decoded: u8,
+    words: [u16; CODEWORDS],
+    errors: usize,
+    parity_errors: usize,
+    // One bit per codeword that failed the parity check after correction
+    parity_failed: u8,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Decoder for packets sent with the given line code
+    pub fn with_line_code(line_code: LineCoding) -> Self {
+        Self {
+            line_code,
+            ..Self::default()
+        }
+    }
+
+    // Decoder for packets sent with the line code and packet layout
+    // of the framing
+    pub fn for_framing(framing: &Framing) -> Self {
+        Self::with_line_code(framing.line_code).with_packet_interleave(framing.packet_interleave)
+    }
+
+    pub fn with_packet_interleave(self, packet_interleave: PacketInterleave) -> Self {
+        Self {
+            packet_interleave,
+            ..self
+        }
+    }
+
+    // Prepare for the next packet
+    pub fn reset(&mut self) {
+        *self = Self {
+            line_code: self.line_code,
+            packet_interleave: self.packet_interleave,
+            ..Self::default()
+        };
+    }
+
+    // Consume a single byte from the radio. Bytes past the end
+    // of the packet are ignored.
+    pub fn push(&mut self, b: u8) {
+        let idx = self.received;
+        if idx >= self.line_code.packet_size() {
+            return;
+        }
+        self.received += 1;
+
+        match self.line_code {
+            LineCoding::Dc6b8b => {
+                // In LASO each 6 bit chunk fills the first unconsumed
+                // byte from its LSb side
+                self.buff |= (strip(b) as u16) << self.buff_cnt;
+                self.buff_cnt += 6;
+
+                if self.buff_cnt >= 8 {
+                    let b = (self.buff & 0xff) as u8;
+                    self.buff >>= 8;
+                    self.buff_cnt -= 8;
+                    self.deinterleave(b);
+                }
+            }
+            // Two radio bytes per byte, high nibble first
+            LineCoding::Manchester => {
+                let nibble = Manchester::compress(b);
+                if idx % 2 == 0 {
+                    self.buff = nibble as u16;
+                } else {
+                    self.deinterleave((self.buff as u8) << 4 | nibble);
+                }
+            }
+            LineCoding::Pn9 => self.deinterleave(b ^ Pn9::SEQUENCE[idx]),
+            LineCoding::Identity => self.deinterleave(b),
+        }
+    }
+
+    pub fn push_slice(&mut self, data: &[u8]) {
+        data.iter().for_each(|b| self.push(*b));
+    }
+
+    // With Spread byte N carries bit N of each codeword, codeword 0 in
+    // the MSb. With Sequential every three bytes are a codeword, MSB first.
+    fn deinterleave(&mut self, b: u8) {
+        let n = self.interleaved;
+        match self.packet_interleave {
+            PacketInterleave::Spread => {
+                for (idx, cw) in self.codewords.iter_mut().enumerate() {
+                    *cw |= (((b >> (7 - idx)) & 0x1) as u32) << n;
+                }
+            }
+            PacketInterleave::Sequential => {
+                self.codewords[n / 3] |= (b as u32) << (8 * (2 - n % 3));
+            }
+        }
+        self.interleaved += 1;
+    }
+
+    // All radio bytes of the packet were received
+    pub fn received(&self) -> bool {
+        self.interleaved >= INTERLEAVED_SIZE
+    }
+
+    // Number of codewords that went through the Golay decoder
+    pub fn decoded(&self) -> usize {
+        self.decoded.count_ones() as usize
+    }
+
+    // All codewords were decoded and the result is available
+    pub fn complete(&self) -> bool {
+        self.decoded() >= CODEWORDS
+    }
+
+    // Decode the next codeword. Returns false when there is nothing
+    // to do, because the packet is either incomplete or already decoded.
+    pub fn step(&mut self) -> bool {
+        if !self.received() || self.complete() {
+            return false;
+        }
+
+        self.word(self.decoded.trailing_ones() as usize);
+
+        true
+    }
+
+    // Return the decoded 12 bit word, running the Golay decoder
+    // for it only when it was not decoded yet.
+    pub fn word(&mut self, idx: usize) -> Option<u16> {
+        if !self.received() || idx >= CODEWORDS {
+            return None;
+        }
+
+        if self.decoded & (1 << idx) == 0 {
+            let (word, err, parity) = PacketWithGolay::undo_golay(self.codewords[idx]);
+            self.words[idx] = word;
+            self.errors += err;
+            if !parity {
+                self.parity_errors += 1;
+                self.parity_failed |= 1 << idx;
+            }
+            self.decoded |= 1 << idx;
+        }
+
+        Some(self.words[idx])
+    }
+
+    // The decoded codeword failed the parity check, the correction
+    // is most likely wrong
+    pub fn parity_failed(&self, idx: usize) -> bool {
+        idx < CODEWORDS && self.parity_failed & (1 << idx) != 0
+    }
+
+    // Return a single byte of the decoded packet data, the status byte
+    // has index 11. Only the codewords carrying the byte are decoded.
+    pub fn byte(&mut self, idx: usize) -> Option<u8> {
+        // Every three bytes are stored in a pair of codewords
+        let first = idx / 3 * 2;
+        match idx % 3 {
+            0 => Some((self.word(first)? >> 4) as u8),
+            1 => Some((((self.word(first)? & 0xf) << 4) | (self.word(first + 1)? >> 8)) as u8),
+            _ => Some(self.word(first + 1)? as u8),
+        }
+    }
+
+    // Finish decoding of the remaining codewords and return the packet
+    // or None when not all radio bytes were received yet.
+    pub fn result(&mut self) -> Option<GolayDecoderResult> {
+        if !self.received() {
+            return None;
+        }
+
+        while self.step() {}
+
+        Some(GolayDecoderResult::from_words(
+            &self.words,
+            &self.codewords,
+            self.errors,
+            self.parity_errors,
+        ))
+    }
+}
+
+impl From<&PacketWithoutDC> for StreamDecoder {
+    fn from(p: &PacketWithoutDC) -> Self {
+        let mut ret = Self::new();
+        ret.push_slice(&p.data());
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::line::LineCode as _;
+    use crate::packet::{test_v2_packet, PacketWithoutDC, TEST_DATA};
+
+    fn test_packet() -> PacketWithoutDC {
+        test_v2_packet(&TEST_DATA).encode_for_transmit()
+    }
+
+    fn assert_same(a: &GolayDecoderResult, b: &GolayDecoderResult) {
+        assert_eq!(a.data, b.data);
+        assert_eq!(a.errors, b.errors);
+        assert_eq!(a.parity_errors, b.parity_errors);
+    }
+
+    #[test]
+    fn test_byte_by_byte() {
+        let radio = test_packet();
+        let expected = radio.decode();
+
+        let mut stream = StreamDecoder::new();
+        for (idx, b) in radio.data().iter().enumerate() {
+            assert!(
+                stream.result().is_none(),
+                "Result available after {idx} bytes"
+            );
+            stream.push(*b);
+        }
+
+        assert!(stream.received());
+        assert_eq!(stream.decoded(), 0);
+        assert!(stream.step());
+        assert_eq!(stream.decoded(), 1);
+
+        assert_same(&stream.result().unwrap(), &expected);
+        assert!(stream.complete());
+        assert!(!stream.step());
+    }
+
+    #[test]
+    fn test_fifo_chunks() {
+        let mut data = test_packet().data();
+        // Burst error, the same as in the corrections test
+        data[3..7].fill(0xff);
+        let expected = PacketWithoutDC::new(&data).decode();
+        assert!(expected.errors > 0);
+
+        for chunk in 1..=4 {
+            let mut stream = StreamDecoder::new();
+            for c in data.chunks(chunk) {
+                stream.push_slice(c);
+                stream.step();
+            }
+            assert_same(&stream.result().unwrap(), &expected);
+
+            stream.reset();
+            assert!(!stream.received());
+        }
+    }
+
+    #[test]
+    fn test_line_codes() {
+        let p = test_v2_packet(&TEST_DATA);
+        for code in [
+            LineCoding::Dc6b8b,
+            LineCoding::Manchester,
+            LineCoding::Pn9,
+            LineCoding::Identity,
+        ] {
+            let mut radio = code.encode_packet(&p);
+            radio[5] ^= 0x80;
+            let expected = code.decode_packet(&radio);
+            assert_eq!(expected.errors, 1, "{code:?}");
+
+            let mut stream = StreamDecoder::with_line_code(code);
+            for c in radio.chunks(3) {
+                stream.push_slice(c);
+                stream.step();
+            }
+            assert_same(&stream.result().unwrap(), &expected);
+
+            // The line code survives the reset
+            stream.reset();
+            stream.push_slice(&radio);
+            assert_same(&stream.result().unwrap(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_packet_interleave() {
+        let p = test_v2_packet(&TEST_DATA);
+        for code in [LineCoding::Dc6b8b, LineCoding::Manchester] {
+            let framing = Framing::NONE
+                .with_line_code(code)
+                .with_packet_interleave(PacketInterleave::Sequential);
+            let mut radio = framing.encode_packet(&p);
+            radio[5] ^= 0x80;
+            let expected = framing.decode_packet(&radio);
+            assert_eq!(expected.data.data, p.data);
+            assert_eq!(expected.errors, 1, "{code:?}");
+
+            let mut stream = StreamDecoder::for_framing(&framing);
+            stream.push_slice(&radio);
+            assert_same(&stream.result().unwrap(), &expected);
+
+            // The layout survives the reset
+            stream.reset();
+            stream.push_slice(&radio);
+            assert_same(&stream.result().unwrap(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let radio = test_packet();
+        let expected = radio.decode();
+
+        let mut stream = StreamDecoder::from(&radio);
+        assert_eq!(stream.byte(11), Some(expected.data.status.encode()));
+        assert_eq!(stream.decoded(), 1);
+        assert_eq!(stream.byte(1), Some(0x01));
+        assert_eq!(stream.decoded(), 3);
+        assert_eq!(stream.byte(0), Some(0x81));
+        assert_eq!(stream.decoded(), 3);
+        assert_eq!(stream.byte(12), None);
+
+        assert_same(&stream.result().unwrap(), &expected);
+    }
+}
@@ -1,14 +1,91 @@
 use crc::{Digest, NoTable};
 use ignore_result::Ignore as _;
 
-use crate::message::Message;
-use crate::packet::{PacketData, PacketStatus, PacketStatusV2};
+use heapless::Vec;
+
+use crate::frame::{Framing, RadioFrame};
+use crate::interleave::{interleave, MAX_INTERLEAVE_DEPTH};
+use crate::message::{Message, MessageVersion};
+use crate::packet::{
+    PacketData, PacketStatus, PacketStatusV2, PacketWithGolay, PacketWithInterleave,
+    PacketWithoutDC,
+};
+use crate::plan::{self, PACKET_DATA_SIZE};
 use crate::rx::LASO_CRC;
 use crate::util::encode_varlength;
 
+// Header fields and payload of a message being sent
+pub trait TxMessage {
+    fn version(&self) -> MessageVersion;
+    fn source_address(&self) -> u32;
+    fn packet_type(&self) -> Option<u32>;
+    fn will_listen(&self) -> bool;
+    fn data(&self) -> &[u8];
+}
+
+impl<const N: usize> TxMessage for Message<N> {
+    fn version(&self) -> MessageVersion {
+        self.version
+    }
+
+    fn source_address(&self) -> u32 {
+        self.source_address
+    }
+
+    fn packet_type(&self) -> Option<u32> {
+        self.packet_type
+    }
+
+    fn will_listen(&self) -> bool {
+        self.will_listen
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+// Message header with a borrowed payload, no copy into Message<N> is needed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageRef<'p> {
+    pub version: MessageVersion,
+    pub data: &'p [u8],
+    pub source_address: u32,
+    pub packet_type: Option<u32>,
+    pub will_listen: bool,
+}
+
+impl<'p> MessageRef<'p> {
+    pub fn sender<'a>(self) -> BorrowedSender<'a, 'p> {
+        Sender::new(self)
+    }
+}
+
+impl TxMessage for MessageRef<'_> {
+    fn version(&self) -> MessageVersion {
+        self.version
+    }
+
+    fn source_address(&self) -> u32 {
+        self.source_address
+    }
+
+    fn packet_type(&self) -> Option<u32> {
+        self.packet_type
+    }
+
+    fn will_listen(&self) -> bool {
+        self.will_listen
+    }
+
+    fn data(&self) -> &[u8] {
+        self.data
+    }
+}
+
 #[derive(Clone)]
-pub struct MessageSender<'a, const N: usize> {
-    message: Message<{ N }>,
+pub struct Sender<'a, M: TxMessage> {
+    message: M,
     // Status template for the next generated packet
     next_status: PacketStatus,
     // Some messages need a second packet even when empty
@@ -17,25 +94,27 @@ pub struct MessageSender<'a, const N: usize> {
     crc8: Digest<'a, u8, NoTable>,
 }
 
-impl<'a, const N: usize> MessageSender<'a, N> {
-    pub fn new(message: Message<N>) -> Self {
-        let version = message.version;
-        let listens = message.will_listen;
+// Sender of an owned Message
+pub type MessageSender<'a, const N: usize> = Sender<'a, Message<N>>;
+
+// Sender of a borrowed payload
+pub type BorrowedSender<'a, 'p> = Sender<'a, MessageRef<'p>>;
+
+impl<'a, M: TxMessage> Sender<'a, M> {
+    pub fn new(message: M) -> Self {
+        let version = message.version();
+        let listens = message.will_listen();
         Self {
             message,
             next_status: match version {
                 #[cfg(feature = "legacy")]
-                crate::message::MessageVersion::LegacyLaso => PacketStatus::legacy(true, true),
-                crate::message::MessageVersion::V2 => {
-                    PacketStatus::V2(PacketStatusV2::default().listens(listens))
-                }
-                crate::message::MessageVersion::V2Short => {
+                MessageVersion::LegacyLaso => PacketStatus::legacy(true, true),
+                MessageVersion::V2 => PacketStatus::V2(PacketStatusV2::default().listens(listens)),
+                MessageVersion::V2Short => {
                     PacketStatus::V2(PacketStatusV2::default().short().listens(listens))
                 }
-                crate::message::MessageVersion::Naked => {
-                    PacketStatus::V2(PacketStatusV2::naked().listens(listens))
-                }
-                crate::message::MessageVersion::NakedShort => {
+                MessageVersion::Naked => PacketStatus::V2(PacketStatusV2::naked().listens(listens)),
+                MessageVersion::NakedShort => {
                     PacketStatus::V2(PacketStatusV2::naked().short().listens(listens))
                 }
             },
@@ -45,8 +124,60 @@ impl<'a, const N: usize> MessageSender<'a, N> {
         }
     }
 
+    pub fn message(&self) -> &M {
+        &self.message
+    }
+
     pub fn data_to_send(&self) -> bool {
-        self.sent < self.message.data.len() || self.force_next
+        self.sent < self.message.data().len() || self.force_next
+    }
+
+    // Number of packets that still need to be sent
+    pub fn packets_needed(&self) -> usize {
+        if !self.data_to_send() {
+            return 0;
+        }
+
+        let remaining = self.message.data().len().saturating_sub(self.sent);
+        let continuations = |capacity: usize, remaining: usize, force: bool| {
+            let n = remaining.div_ceil(capacity);
+            if force {
+                n.max(1)
+            } else {
+                n
+            }
+        };
+
+        match self.next_status {
+            #[cfg(feature = "legacy")]
+            PacketStatus::Legacy(legacy) if legacy.first => self.packets_for_message(),
+            PacketStatus::V2(_) => self.packets_for_message(),
+            PacketStatus::Data(_) => {
+                continuations(PACKET_DATA_SIZE + 1, remaining, self.force_next)
+            }
+            _ => continuations(PACKET_DATA_SIZE, remaining, self.force_next),
+        }
+    }
+
+    // Number of packets for the whole message, before the first one is sent
+    fn packets_for_message(&self) -> usize {
+        let version = self.message.version();
+        let header = plan::header_size(
+            version,
+            self.message.source_address(),
+            self.message.packet_type(),
+        );
+        plan::packets(version, header, self.message.data().len())
+    }
+
+    // Iterate over the packets with the preamble and sync word applied
+    pub fn frames<'s>(self, framing: Framing<'s>) -> FrameIter<'a, 's, M> {
+        FrameIter {
+            sender: self,
+            framing,
+            block: Vec::new(),
+            next: 0,
+        }
     }
 
     pub fn packet(&mut self) -> PacketData {
@@ -62,7 +193,7 @@ impl<'a, const N: usize> MessageSender<'a, N> {
                 if legacy.first {
                     // Queue source address and packet type
                     // First packet always has enough space for this
-                    match self.message.packet_type {
+                    match self.message.packet_type() {
                         Some(t) => {
                             encode_varlength(t, |b| {
                                 p.data.push(b).ignore();
@@ -70,7 +201,7 @@ impl<'a, const N: usize> MessageSender<'a, N> {
                         }
                         None => p.data.push(0x00_u8).ignore(),
                     }
-                    encode_varlength(self.message.source_address as u32, |b| {
+                    encode_varlength(self.message.source_address(), |b| {
                         p.data.push(b).ignore();
                     });
                 }
@@ -81,7 +212,7 @@ impl<'a, const N: usize> MessageSender<'a, N> {
                 if !v2.naked {
                     // Queue source address and packet type
                     // First packet always has enough space for this
-                    match self.message.packet_type {
+                    match self.message.packet_type() {
                         Some(t) => {
                             encode_varlength(t, |b| {
                                 p.data.push(b).ignore();
@@ -91,7 +222,7 @@ impl<'a, const N: usize> MessageSender<'a, N> {
                     }
                 }
 
-                encode_varlength(self.message.source_address, |b| {
+                encode_varlength(self.message.source_address(), |b| {
                     p.data.push(b).ignore();
                 });
 
@@ -123,9 +254,9 @@ impl<'a, const N: usize> MessageSender<'a, N> {
         };
 
         // Fill in data
-        while p.data.len() < capacity && self.sent < self.message.data.len() {
+        while p.data.len() < capacity && self.sent < self.message.data().len() {
             p.data
-                .push(*self.message.data.get(self.sent).unwrap())
+                .push(*self.message.data().get(self.sent).unwrap())
                 .unwrap();
             self.sent += 1;
         }
@@ -143,7 +274,7 @@ impl<'a, const N: usize> MessageSender<'a, N> {
 
         // Add one extra data byte when in naked mode
         if let PacketStatus::Data(data) = &mut p.status {
-            *data = *self.message.data.get(self.sent).unwrap_or(&0x00);
+            *data = *self.message.data().get(self.sent).unwrap_or(&0x00);
             self.sent += 1;
         }
 
@@ -169,3 +300,73 @@ impl<'a, const N: usize> MessageSender<'a, N> {
         p
     }
 }
+
+// Radio packets ready for transmission
+impl<'a, M: TxMessage> Iterator for Sender<'a, M> {
+    type Item = PacketWithoutDC;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.data_to_send() {
+            return None;
+        }
+
+        Some(self.packet().encode_for_transmit())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.packets_needed();
+        (n, Some(n))
+    }
+}
+
+impl<'a, M: TxMessage> ExactSizeIterator for Sender<'a, M> {}
+
+// Radio packets with framing, see Sender::frames
+#[derive(Clone)]
+pub struct FrameIter<'a, 's, M: TxMessage> {
+    sender: Sender<'a, M>,
+    framing: Framing<'s>,
+    // Interleaved block being sent and the next frame of it
+    block: Vec<PacketWithInterleave, MAX_INTERLEAVE_DEPTH>,
+    next: usize,
+}
+
+impl<'a, 's, M: TxMessage> FrameIter<'a, 's, M> {
+    pub fn packets_needed(&self) -> usize {
+        self.sender.packets_needed() + self.block.len() - self.next
+    }
+
+    // Encode the next framing.interleave_depth() packets, or whatever
+    // is left of the message
+    fn fill_block(&mut self) {
+        self.block.clear();
+        self.next = 0;
+        while self.block.len() < self.framing.interleave_depth() && self.sender.data_to_send() {
+            let p = PacketWithGolay::from(&self.sender.packet());
+            let p = self.framing.packet_interleave.interleave(&p);
+            self.block.push(p).ignore();
+        }
+        interleave(&mut self.block);
+    }
+}
+
+impl<'a, 's, M: TxMessage> Iterator for FrameIter<'a, 's, M> {
+    type Item = RadioFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.block.len() {
+            self.fill_block();
+        }
+
+        let p = self.block.get(self.next)?;
+        self.next += 1;
+        Some(self.framing.frame_interleaved(p))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.packets_needed();
+        (n, Some(n))
+    }
+}
+
+impl<'a, 's, M: TxMessage> ExactSizeIterator for FrameIter<'a, 's, M> {}
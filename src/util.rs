@@ -6,6 +6,16 @@ pub fn encode_varlength(mut val: u32, mut consumer: impl FnMut(u8)) {
     consumer(val as u8);
 }
 
+// Number of bytes used by encode_varlength
+pub const fn varlength_size(mut val: u32) -> usize {
+    let mut size = 1;
+    while val >= 0x80 {
+        size += 1;
+        val >>= 7;
+    }
+    size
+}
+
 // Compute u16 with the same representation as varlength(val_u16)
 // This only works for 0x80..=0x3999
 pub const fn encode_id(mut val: u16) -> u16 {
@@ -19,12 +29,23 @@ pub const fn encode_id(mut val: u16) -> u16 {
 }
 
 pub fn decode_extended_number(data: &[u8], start: usize) -> (u32, usize) {
+    // Cannot fail, only indices below data.len() are read
+    decode_extended_number_with(data.len(), start, |idx| data.get(idx).copied()).unwrap()
+}
+
+// Same as decode_extended_number, the bytes below len are read through
+// the accessor. Returns None when the accessor fails.
+pub fn decode_extended_number_with(
+    len: usize,
+    start: usize,
+    mut byte: impl FnMut(usize) -> Option<u8>,
+) -> Option<(u32, usize)> {
     // LSB first, MSb marks extended value
     let mut val = 0_u32;
     let mut shift = 0_u8;
     let mut idx = start;
-    while shift < 16 && idx < data.len() {
-        let b = data[idx] as u32;
+    while shift < 16 && idx < len {
+        let b = byte(idx)? as u32;
         val += (b & 0x7F) << shift;
         shift += 7;
         idx += 1;
@@ -33,7 +54,7 @@ pub fn decode_extended_number(data: &[u8], start: usize) -> (u32, usize) {
             break;
         }
     }
-    (val, idx)
+    Some((val, idx))
 }
 
 pub struct IntoLeastSigByte(u8);
@@ -63,7 +84,29 @@ impl From<u32> for IntoLeastSigByte {
 
 #[cfg(test)]
 mod test {
-    use crate::{message::Message, util::encode_id};
+    use crate::{
+        message::Message,
+        util::{encode_id, encode_varlength, varlength_size},
+    };
+
+    #[test]
+    fn test_varlength_size() {
+        for v in [
+            0,
+            1,
+            0x7f,
+            0x80,
+            0x3fff,
+            0x4000,
+            0x1fffff,
+            0x200000,
+            u32::MAX,
+        ] {
+            let mut size = 0;
+            encode_varlength(v, |_| size += 1);
+            assert_eq!(varlength_size(v), size, "bad size for 0x{v:x}");
+        }
+    }
 
     #[test]
     fn test_encode_id() {
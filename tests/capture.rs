@@ -0,0 +1,88 @@
+#![cfg(feature = "std")]
+#![allow(clippy::field_reassign_with_default)]
+
+use futures_lite::future::block_on;
+use laso_packet::{
+    capture::{CaptureReader, CaptureRecord, CaptureWriter, IoSink, Replay},
+    frame::Framing,
+    link::{receive_message, LinkTiming, RxPacket},
+    message::{Message, MessageVersion},
+    rx::RxMessage,
+    tx::MessageSender,
+};
+
+fn test_message(version: MessageVersion, source: u32, len: usize) -> Message<64> {
+    let mut msg = Message::default();
+    msg.version = version;
+    msg.source_address = source;
+    msg.packet_type = Some(0x10);
+    msg.data.extend((0..len).map(|b| b as u8));
+    msg
+}
+
+// Record the packets of the messages, the messages start at the given times
+// and their packets follow each other with the inter packet gap
+fn capture(messages: &[(u64, Message<64>)], timing: &LinkTiming) -> Vec<u8> {
+    let mut w = CaptureWriter::new(IoSink(Vec::new())).unwrap();
+    for (start, msg) in messages {
+        let sender = MessageSender::new(msg.clone());
+        for (n, frame) in sender.frames(Framing::NONE).enumerate() {
+            let mut rx = RxPacket::init();
+            rx.packet.extend_from_slice(&frame).unwrap();
+            rx.rssi = 0x40 + n as u8;
+            let at = start + n as u64 * timing.inter_packet_us as u64;
+            w.write(&CaptureRecord::from_rx(&rx, at, 868_300_000, 7))
+                .unwrap();
+        }
+    }
+    w.into_inner().0
+}
+
+fn replay(data: &[u8], timing: &LinkTiming) -> Vec<RxMessage<64>> {
+    let mut replay = Replay::new(CaptureReader::new(data).unwrap());
+    let mut out = Vec::new();
+    while !replay.finished() {
+        if let Some(msg) = block_on(receive_message(&mut replay, timing, u32::MAX)).unwrap() {
+            assert_eq!(replay.last_record().unwrap().gateway_id, 7);
+            out.push(msg);
+        }
+    }
+    out
+}
+
+#[test]
+fn test_replay() {
+    let timing = LinkTiming::default();
+    let a = test_message(MessageVersion::V2, 0x42, 31);
+    let b = test_message(MessageVersion::V2, 0x43, 20);
+    let c = test_message(MessageVersion::V2Short, 0x44, 8);
+
+    let data = capture(
+        &[
+            (1_000, a.clone()),
+            (500_000, b.clone()),
+            (501_000_000, c.clone()),
+        ],
+        &timing,
+    );
+    let rx = replay(&data, &timing);
+    assert_eq!(rx.len(), 3);
+    for (rx, msg) in rx.iter().zip([a, b, c]) {
+        assert_eq!(rx.msg.source_address, msg.source_address);
+        assert!(rx.msg.data.starts_with(&msg.data));
+    }
+    assert_eq!(rx[2].rssi, 0x40);
+}
+
+#[test]
+fn test_replay_boundaries() {
+    let timing = LinkTiming::default();
+    let a = test_message(MessageVersion::V2, 0x42, 31);
+    let b = test_message(MessageVersion::V2, 0x43, 20);
+
+    // The second message follows within the packet timeout, a live receiver
+    // takes its packets as part of the first message and fails
+    let data = capture(&[(1_000, a), (50_000, b)], &timing);
+    let mut replay = Replay::new(CaptureReader::new(&data[..]).unwrap());
+    assert!(block_on(receive_message::<_, 64>(&mut replay, &timing, u32::MAX)).is_err());
+}
@@ -0,0 +1,264 @@
+#![cfg(feature = "std")]
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+fn laso_bytes(args: &[&str], stdin: impl AsRef<[u8]>) -> Vec<u8> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_laso"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_ref())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "laso {args:?} failed");
+    out.stdout
+}
+
+fn laso(args: &[&str], stdin: &str) -> String {
+    String::from_utf8(laso_bytes(args, stdin)).unwrap()
+}
+
+// Runs a command that is expected to fail, returns its error message
+fn laso_fails(args: &[&str], stdin: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_laso"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    assert_eq!(out.status.code(), Some(1), "laso {args:?} did not fail");
+    String::from_utf8(out.stderr).unwrap()
+}
+
+#[test]
+fn test_encode_decode() {
+    let frames = laso(
+        &[
+            "encode",
+            "--source",
+            "0x1234",
+            "--type",
+            "16",
+            "--preamble",
+            "2",
+            "--sync",
+            "2dd4",
+            "0102030405060708090a0b0c0d0e0f",
+        ],
+        "",
+    );
+    assert_eq!(frames.lines().count(), 2);
+    assert!(frames.lines().all(|l| l.starts_with("aaaa2dd4")));
+
+    let out = laso(&["decode", "--skip", "4"], &frames);
+    assert!(out.contains("  source       0x1234"));
+    assert!(out.contains("  type         0x10"));
+    assert!(out.contains("  rx           ok, CRC8P"));
+    assert!(out.contains("  payload      0102030405060708090a0b0c0d0e0f"));
+    assert!(out.contains("  confidence   Clean"));
+
+    // Binary frames only with --binary, a bad line is reported
+    let binary: Vec<u8> = frames
+        .lines()
+        .flat_map(|l| (0..l.len()).step_by(2).map(move |i| &l[i..i + 2]))
+        .map(|b| u8::from_str_radix(b, 16).unwrap())
+        .collect();
+    let out = laso_bytes(&["decode", "--skip", "4", "--binary"], binary);
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("  payload      0102030405060708090a0b0c0d0e0f"));
+
+    let err = laso_fails(&["decode"], &format!("{frames}zz\n"));
+    assert!(err.contains("line 3: not a hex frame"));
+}
+
+#[test]
+fn test_explain() {
+    let frames = laso(&["encode", "--version", "v2short", "0102"], "");
+    // Flip the first bit
+    let first = u8::from_str_radix(&frames[..2], 16).unwrap() ^ 0x80;
+    let frame = format!("{first:02x}{}", frames[2..].trim());
+
+    let out = laso(&["explain", &frame], "");
+    assert_eq!(out.lines().count(), 32 * 9);
+    assert_eq!(out.matches("dc balance").count(), 64);
+    assert_eq!(out.lines().nth(1).unwrap().matches("corrected").count(), 1);
+}
+
+#[test]
+fn test_json() {
+    let mut frames = laso(
+        &[
+            "encode",
+            "--source",
+            "0x1234",
+            "--type",
+            "16",
+            "0102030405060708090a0b0c0d0e0f",
+        ],
+        "",
+    );
+    frames += &laso(
+        &["encode", "--version", "nakedshort", "--source", "7", "0a0b"],
+        "",
+    );
+
+    let out = laso(&["json"], &frames);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(
+        r#"{"model":"LASO-V2","id":4660,"packet_type":16,"data":"0102030405060708090a0b0c0d0e0f"#
+    ));
+    assert!(lines[0].ends_with(r#""mic":"PASS"}"#));
+    assert!(lines[1].starts_with(r#"{"model":"LASO-NakedShort","id":7,"data":"0a0b"#));
+
+    // The first message ends without its CRC packet
+    let frames: Vec<&str> = frames.lines().collect();
+    let out = laso(&["json"], frames[0]);
+    assert_eq!(
+        out.trim(),
+        r#"{"model":"LASO-V2","id":4660,"packet_type":16,"rssi":0,"lna":0,"errors":0,"mic":"FAIL","error":"Truncated"}"#
+    );
+
+    // The naked packet takes the place of the CRC packet
+    let out = laso(&["json"], &[frames[0], frames[2]].join("\n"));
+    assert!(out.trim().ends_with(r#""mic":"FAIL","error":"CrcFailed"}"#));
+}
+
+#[test]
+fn test_pcap() {
+    let frames = laso(
+        &[
+            "encode",
+            "--source",
+            "0x1234",
+            "0102030405060708090a0b0c0d0e0f",
+        ],
+        "",
+    );
+    let pcap = laso_bytes(&["pcap"], &frames);
+    assert_eq!(pcap.len(), 24 + 2 * (16 + 76));
+    assert_eq!(&pcap[20..24], &[147, 0, 0, 0]);
+    // Status kinds of both packets, V2 and CRC8P
+    assert_eq!(pcap[24 + 16 + 1], 1);
+    assert_eq!(pcap[24 + 16 + 76 + 16 + 1], 2);
+
+    let lua = laso(&["dissector"], "");
+    assert!(lua.contains("DissectorTable.get(\"wtap_encap\"):add(wtap.USER0, laso)"));
+}
+
+#[test]
+fn test_modem() {
+    let frames = laso(
+        &[
+            "encode",
+            "--preamble",
+            "4",
+            "--sync",
+            "2dd4",
+            "--source",
+            "0x1234",
+            "0102030405060708090a0b0c0d0e0f",
+        ],
+        "",
+    );
+
+    let path = std::env::temp_dir().join(format!("laso_cli_{}.cs16", std::process::id()));
+    let path = path.to_str().unwrap();
+    let modem = ["--format", "cs16", "--rate", "250000", "--bitrate", "9600"];
+    laso(&[&["modulate"], &modem[..], &[path]].concat(), &frames);
+    let packets = laso(&[&["demodulate"], &modem[..], &[path]].concat(), "");
+    std::fs::remove_file(path).unwrap();
+
+    // Without preamble and sync word
+    let sent: Vec<&str> = frames.lines().map(|l| &l[12..]).collect();
+    assert_eq!(packets.lines().collect::<Vec<_>>(), sent);
+}
+
+#[cfg(feature = "sim")]
+#[test]
+fn test_ber_sweep_no_messages() {
+    for args in [["--messages", "0"], ["--len", "0"]] {
+        let out = Command::new(env!("CARGO_BIN_EXE_ber_sweep"))
+            .args(args)
+            .stderr(Stdio::null())
+            .output()
+            .unwrap();
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        assert!(out.stdout.is_empty());
+    }
+}
+
+#[test]
+fn test_line_code() {
+    let frames = laso(
+        &[
+            "encode",
+            "--line-code",
+            "manchester",
+            "--source",
+            "7",
+            "0a0b",
+        ],
+        "",
+    );
+    assert!(frames.lines().all(|f| f.len() == 2 * 48));
+
+    let out = laso(&["json", "--line-code", "manchester"], &frames);
+    assert!(out.starts_with(r#"{"model":"LASO-V2","id":7,"packet_type":0,"data":"0a0b"#));
+    assert!(out.trim().ends_with(r#""mic":"PASS"}"#));
+
+    // 16 bytes of preamble and sync word in front of a 48 byte packet
+    let args = ["encode", "--line-code", "manchester", "--sync", "2dd4"];
+    let framed = laso(&[&args[..], &["--preamble", "14", "0a0b"]].concat(), "");
+    assert!(framed.lines().all(|f| f.len() == 2 * 64));
+    let err = laso_fails(&[&args[..], &["--preamble", "15", "0a0b"]].concat(), "");
+    assert!(err.contains("limited to 64 bytes"));
+
+    let first = frames.lines().next().unwrap();
+    let out = laso(&["explain", "--line-code", "manchester", first], "");
+    assert_eq!(out.lines().count(), 48 * 9);
+    assert_eq!(out.matches("manchester second half").count(), 48 * 4);
+    assert_eq!(out.matches("codeword 0 bit").count(), 24);
+}
+
+#[test]
+fn test_packet_interleave() {
+    let args = ["--line-code", "pn9", "--packet-interleave", "sequential"];
+    let frames = laso(
+        &[&["encode"], &args[..], &["--source", "7", "0a0b"]].concat(),
+        "",
+    );
+
+    let out = laso(&[&["json"], &args[..]].concat(), &frames);
+    assert!(out.starts_with(r#"{"model":"LASO-V2","id":7,"packet_type":0,"data":"0a0b"#));
+    let out = laso(&["json", "--line-code", "pn9"], &frames);
+    assert!(!out.contains(r#""data":"0a0b""#));
+
+    let out = laso(&[&["decode"], &args[..]].concat(), &frames);
+    assert!(out.contains("  source       0x7"));
+
+    // Every radio byte belongs to a single codeword, the first three
+    // bytes to codeword 0
+    let first = frames.lines().next().unwrap();
+    let out = laso(&[&["explain"], &args[..], &[first]].concat(), "");
+    assert_eq!(out.matches("codeword 0 bit").count(), 24);
+    let first_bytes = out.lines().take(3 * 9).collect::<Vec<_>>().join("\n");
+    assert_eq!(first_bytes.matches("codeword 0 bit").count(), 24);
+}
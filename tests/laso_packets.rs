@@ -1,10 +1,25 @@
+#![allow(clippy::field_reassign_with_default)]
+
+#[macro_use(quickcheck)]
+extern crate quickcheck_macros;
+
 use futures_lite::future::block_on;
 use laso_packet::{
     behavior::decode_with_breaks,
+    frame::Framing,
+    interleave::PacketInterleave,
     laso::LasoPacketType,
+    line::{LineCode as _, LineCoding},
     message::{Message, MessageVersion},
-    rx::RxMessageDecoder,
-    tx::MessageSender,
+    packet::{PacketStatus, PacketStatusV2},
+    plan::TxPlan,
+    raw::RawReceiveData,
+    rx::{
+        decode_coded, decode_raw, PacketHeader, RxErrorKind, RxMessage, RxMessageDecoder,
+        RxRingDecoder, RxViewDecoder,
+    },
+    stream::StreamDecoder,
+    tx::{MessageRef, MessageSender},
 };
 
 fn test_msg_reversal<const N: usize>(msg: &Message<N>) {
@@ -233,3 +248,410 @@ pub fn test_short_long_naked_reversal() {
     }
     test_msg_reversal(&msg);
 }
+
+fn long_v2_message() -> Message<22> {
+    let mut msg: Message<22> = Message::default();
+    msg.source_address = 0x55;
+    msg.packet_type = Some(LasoPacketType::GsmStatus.into());
+    msg.version = MessageVersion::V2;
+    msg.add(0x01_u8);
+    msg.add(0x0203_u16);
+    msg.add(0x0405_u16);
+    msg.add(0x0607_u16);
+    msg.add(0x0809_u16);
+    msg.add(0x0a0b_u16);
+    // Padding
+    for _ in 0..9 {
+        msg.add(0x00_u8);
+    }
+    msg
+}
+
+#[test]
+pub fn test_raw_capture_per_packet() {
+    let msg = long_v2_message();
+
+    let mut captures: Vec<RawReceiveData<32>> = Vec::new();
+    let mut sender = MessageSender::new(msg.clone());
+    while sender.data_to_send() {
+        let mut raw = RawReceiveData::init();
+        raw.packet
+            .extend_from_slice(&sender.packet().encode_for_transmit().data())
+            .unwrap();
+        raw.rssi = 10 + 10 * captures.len() as u8;
+        raw.lna = 1;
+        captures.push(raw);
+    }
+    assert_eq!(captures.len(), 2);
+
+    let rx: RxMessage<22> = decode_raw(&captures).expect("Rx decode error");
+    assert_eq!(msg, rx.msg);
+    assert_eq!(rx.rssi, 15);
+    assert_eq!(rx.lna, 1);
+    assert_eq!(rx.errors, 0);
+
+    // The message stops before its last packet
+    let err = decode_raw::<22, 32>(&captures[..1]).err().unwrap();
+    assert_eq!(err.kind, RxErrorKind::Truncated);
+    assert_eq!(err.packet, 1);
+}
+
+#[test]
+pub fn test_raw_capture_multiple_packets() {
+    let msg = long_v2_message();
+
+    let mut raw: RawReceiveData<64> = RawReceiveData::init();
+    raw.rssi = 42;
+    let mut sender = MessageSender::new(msg.clone());
+    while sender.data_to_send() {
+        raw.packet
+            .extend_from_slice(&sender.packet().encode_for_transmit().data())
+            .unwrap();
+    }
+
+    let rx: RxMessage<22> = decode_raw([&raw]).expect("Rx decode error");
+    assert_eq!(msg, rx.msg);
+    assert_eq!(rx.rssi, 42);
+
+    // Drop the tail of the second packet
+    raw.packet.truncate(40);
+    assert_eq!(
+        decode_raw::<22, 64>([&raw]).err().map(|err| err.kind),
+        Some(RxErrorKind::Truncated)
+    );
+}
+
+#[test]
+pub fn test_peek_header() {
+    let mut msg: Message<22> = Message::default();
+    msg.source_address = 0x1234;
+    msg.packet_type = Some(0x102);
+    msg.version = MessageVersion::V2;
+    // Fill both packets after the 4 byte header
+    for b in 0..18 {
+        msg.add(b as u8);
+    }
+
+    let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+    let mut sender = MessageSender::new(msg.clone());
+
+    // The first packet carries the header
+    let mut stream = StreamDecoder::from(&sender.packet().encode_for_transmit());
+    let header = rx.peek_header(&mut stream).expect("Rx header decode error");
+    assert_eq!(
+        header,
+        PacketHeader {
+            status: PacketStatus::V2(PacketStatusV2::default()),
+            packet_type: Some(0x102),
+            source_address: Some(0x1234),
+        }
+    );
+    // Only the status and header codewords were decoded
+    assert!(stream.decoded() < 8);
+    rx.append(&stream.result().unwrap())
+        .expect("Rx decode error");
+
+    // The follow-up packet has no header
+    let mut stream = StreamDecoder::from(&sender.packet().encode_for_transmit());
+    let header = rx.peek_header(&mut stream).expect("Rx header decode error");
+    assert!(matches!(header.status, PacketStatus::CRC8P(_)));
+    assert_eq!(header.packet_type, None);
+    assert_eq!(header.source_address, None);
+    rx.append(&stream.result().unwrap())
+        .expect("Rx decode error");
+
+    assert!(!sender.data_to_send());
+    assert_eq!(msg, rx.msg);
+}
+
+#[test]
+pub fn test_sequential_layout() {
+    let msg = long_v2_message();
+    let framing = Framing::NONE
+        .with_line_code(LineCoding::Manchester)
+        .with_packet_interleave(PacketInterleave::Sequential);
+
+    let mut captures: Vec<RawReceiveData<48>> = Vec::new();
+    let mut sender = MessageSender::new(msg.clone());
+    while sender.data_to_send() {
+        let mut raw = RawReceiveData::init();
+        raw.packet
+            .extend_from_slice(&framing.encode_packet(&sender.packet()))
+            .unwrap();
+        captures.push(raw);
+    }
+
+    let mut stream = StreamDecoder::for_framing(&framing);
+    stream.push_slice(&captures[0].packet);
+    let rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+    let header = rx.peek_header(&mut stream).expect("Rx header decode error");
+    assert_eq!(header.source_address, Some(msg.source_address));
+    assert_eq!(header.packet_type, msg.packet_type);
+
+    let rx: RxMessage<22> = decode_coded(&framing, &captures).expect("Rx decode error");
+    assert_eq!(msg, rx.msg);
+    // The layout is part of the framing, Spread decodes garbage
+    let spread = decode_coded::<22, 48>(&framing.line_code, &captures);
+    assert_ne!(spread.ok().map(|rx| rx.msg), Some(msg));
+}
+
+#[test]
+pub fn test_peek_naked_header() {
+    let mut msg: Message<23> = Message::default();
+    msg.source_address = 0x55;
+    msg.version = MessageVersion::NakedShort;
+    msg.will_listen = true;
+    msg.add(0x01_u8);
+
+    let rx: RxMessageDecoder<23> = RxMessageDecoder::default();
+    let mut sender = MessageSender::new(msg);
+    let mut stream = StreamDecoder::from(&sender.packet().encode_for_transmit());
+    let header = rx.peek_header(&mut stream).expect("Rx header decode error");
+    assert_eq!(
+        header.status,
+        PacketStatus::V2(PacketStatusV2 {
+            short: true,
+            naked: true,
+            listens: true
+        })
+    );
+    assert_eq!(header.packet_type, None);
+    assert_eq!(header.source_address, Some(0x55));
+}
+
+#[test]
+pub fn test_view_decoder() {
+    let msg = long_v2_message();
+
+    let mut buffer = [0_u8; 64];
+    let mut rx = RxViewDecoder::with_buffer(&mut buffer);
+    let mut sender = MessageSender::new(msg.clone());
+    while sender.data_to_send() {
+        let p = sender.packet().encode_for_transmit().decode();
+        rx.append(&p).expect("Rx decode error");
+    }
+
+    let view = rx.view().expect("Message not available");
+    assert_eq!(view.version, msg.version);
+    assert_eq!(view.source_address, msg.source_address);
+    assert_eq!(view.packet_type, msg.packet_type);
+    assert_eq!(view.payload, msg.data.as_slice());
+
+    rx.reset();
+    assert!(rx.view().is_none());
+}
+
+#[test]
+pub fn test_view_decoder_full() {
+    let msg = long_v2_message();
+
+    let mut buffer = [0_u8; 12];
+    let mut rx = RxViewDecoder::with_buffer(&mut buffer);
+    let mut sender = MessageSender::new(msg);
+    rx.append(&sender.packet().encode_for_transmit().decode())
+        .expect("Rx decode error");
+    let err = rx
+        .append(&sender.packet().encode_for_transmit().decode())
+        .unwrap_err();
+    assert_eq!(err.kind, RxErrorKind::Full);
+    assert_eq!(err.packet, 1);
+}
+
+fn receive_into<S: laso_packet::rx::RxStorage>(
+    rx: &mut laso_packet::rx::RxDecoder<'_, S>,
+    msg: &Message<22>,
+) -> Result<(), RxErrorKind> {
+    let mut sender = MessageSender::new(msg.clone());
+    while sender.data_to_send() {
+        let p = sender.packet().encode_for_transmit().decode();
+        rx.append_with_repair(&p, 8).map_err(|e| e.kind)?;
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_ring_decoder() {
+    let msg = long_v2_message();
+    let payload = msg.data.as_slice();
+
+    let mut buffer = [0_u8; 50];
+    let mut rx: RxRingDecoder<'_, '_, 4> = RxRingDecoder::with_ring(&mut buffer);
+    assert!(!rx.commit());
+
+    for _ in 0..2 {
+        receive_into(&mut rx, &msg).unwrap();
+        assert!(rx.commit());
+    }
+    assert_eq!(rx.msg.queued(), 2);
+
+    let first = rx.msg.peek().expect("Message not queued");
+    assert_eq!(first.source_address, msg.source_address);
+    assert_eq!(first.packet_type, msg.packet_type);
+    assert_eq!(first.payload, (payload, &[][..]));
+    assert!(rx.msg.pop());
+
+    // The third message wraps around the end of the buffer
+    receive_into(&mut rx, &msg).unwrap();
+    assert!(rx.commit());
+
+    // Only 10 bytes left before the second message
+    assert_eq!(receive_into(&mut rx, &msg), Err(RxErrorKind::Full));
+    rx.reset();
+    assert_eq!(rx.msg.queued(), 2);
+
+    assert!(rx.msg.pop());
+    let third = rx.msg.peek().expect("Message not queued");
+    assert_eq!(third.payload, (&payload[..10], &payload[10..]));
+    assert!(rx.msg.pop());
+    assert!(!rx.msg.pop());
+}
+
+#[test]
+pub fn test_zero_copy_will_listen() {
+    let mut msg = long_v2_message();
+    msg.will_listen = true;
+
+    let mut buffer = [0_u8; 64];
+    let mut rx = RxViewDecoder::with_buffer(&mut buffer);
+    receive_into(&mut rx, &msg).unwrap();
+    assert!(rx.view().expect("Message not available").will_listen);
+    rx.reset();
+    msg.will_listen = false;
+    receive_into(&mut rx, &msg).unwrap();
+    assert!(!rx.view().expect("Message not available").will_listen);
+
+    let mut buffer = [0_u8; 64];
+    let mut rx: RxRingDecoder<'_, '_, 2> = RxRingDecoder::with_ring(&mut buffer);
+    for will_listen in [true, false] {
+        msg.will_listen = will_listen;
+        receive_into(&mut rx, &msg).unwrap();
+        assert!(rx.commit());
+    }
+    assert!(rx.msg.peek().expect("Message not queued").will_listen);
+    assert!(rx.msg.pop());
+    assert!(!rx.msg.peek().expect("Message not queued").will_listen);
+}
+
+#[test]
+pub fn test_view_decoder_repair() {
+    let msg = long_v2_message();
+    let mut buffer = [0_u8; 64];
+    let mut rx = RxViewDecoder::with_buffer(&mut buffer);
+    receive_into(&mut rx, &msg).unwrap();
+    let view = rx.view().expect("Message not available");
+    assert_eq!(view.payload, msg.data.as_slice());
+}
+
+fn all_versions() -> Vec<MessageVersion> {
+    vec![
+        #[cfg(feature = "legacy")]
+        MessageVersion::LegacyLaso,
+        MessageVersion::V2,
+        MessageVersion::V2Short,
+        MessageVersion::Naked,
+        MessageVersion::NakedShort,
+    ]
+}
+
+#[quickcheck]
+fn prop_packets_needed(len: u8, source_address: u32, packet_type: Option<u32>) -> bool {
+    all_versions().into_iter().all(|version| {
+        let msg = sender_message(version, source_address, packet_type, len);
+        let expected = MessageSender::new(msg.clone()).count();
+
+        // Check the estimate before every packet
+        let mut sender = MessageSender::new(msg);
+        let mut sent = 0;
+        loop {
+            if sender.packets_needed() != expected - sent || sender.len() != expected - sent {
+                return false;
+            }
+            if sender.next().is_none() {
+                return sent == expected;
+            }
+            sent += 1;
+        }
+    })
+}
+
+#[quickcheck]
+fn prop_plan_matches_sender(len: u8, source_address: u32, packet_type: Option<u32>) -> bool {
+    let framing = Framing::new(4, &[0x2d, 0xd4]);
+    all_versions().into_iter().all(|version| {
+        let msg = sender_message(version, source_address, packet_type, len);
+        let plan = TxPlan::for_message(&msg, &framing, 4800);
+        let frames: Vec<_> = MessageSender::new(msg).frames(framing).collect();
+
+        plan.packets == frames.len() && plan.bytes == frames.iter().map(|f| f.len()).sum::<usize>()
+    })
+}
+
+fn sender_message(
+    version: MessageVersion,
+    source_address: u32,
+    packet_type: Option<u32>,
+    len: u8,
+) -> Message<64> {
+    let mut msg: Message<64> = Message::default();
+    msg.version = version;
+    msg.source_address = source_address;
+    msg.packet_type = packet_type;
+    for b in 0..len % 64 {
+        msg.add(b);
+    }
+    msg
+}
+
+#[test]
+pub fn test_sender_iterator() {
+    let msg = long_v2_message();
+
+    let mut sender = MessageSender::new(msg.clone());
+    let mut expected = Vec::new();
+    while sender.data_to_send() {
+        expected.push(sender.packet().encode_for_transmit());
+    }
+
+    let packets: Vec<_> = msg.clone().sender().collect();
+    assert_eq!(packets, expected);
+
+    let framing = Framing::new(3, &[0x2d, 0xd4]);
+    let frames = msg.sender().frames(framing);
+    assert_eq!(frames.packets_needed(), 2);
+    for (frame, packet) in frames.zip(expected.iter()) {
+        assert_eq!(&frame[..5], &[0xaa, 0xaa, 0xaa, 0x2d, 0xd4]);
+        assert_eq!(&frame[5..], &packet.data());
+    }
+}
+
+#[quickcheck]
+fn prop_borrowed_sender_identical(
+    version: u8,
+    data: Vec<u8>,
+    source_address: u32,
+    packet_type: Option<u32>,
+    will_listen: bool,
+) -> bool {
+    let versions = all_versions();
+    let mut msg: Message<64> = Message::default();
+    msg.version = versions[version as usize % versions.len()];
+    msg.source_address = source_address;
+    msg.packet_type = packet_type;
+    msg.will_listen = will_listen;
+    for b in data.iter().take(64) {
+        msg.add(*b);
+    }
+
+    let owned: Vec<_> = MessageSender::new(msg.clone()).collect();
+
+    let payload = &data[..data.len().min(64)];
+    let borrowed = MessageRef {
+        version: msg.version,
+        data: payload,
+        source_address,
+        packet_type,
+        will_listen,
+    };
+    owned == borrowed.sender().collect::<Vec<_>>()
+        && owned == msg.as_message_ref().sender().collect::<Vec<_>>()
+}
@@ -1,13 +1,33 @@
+#![allow(clippy::field_reassign_with_default)]
+
 use futures_lite::future::block_on;
 use laso_packet::{
     behavior::decode_with_breaks,
     laso::LasoPacketType,
     message::{Message, MessageVersion},
+    packet::PacketWithoutDC,
     rx::RxMessageDecoder,
     tx::MessageSender,
 };
 
+// Simulate a burst error affecting 1/8 of the message
+fn burst(p: &PacketWithoutDC) -> Vec<u8> {
+    let mut radio_data = p.data();
+    radio_data[3] = 0xff;
+    radio_data[4] = 0xff;
+    radio_data[5] = 0xff;
+    radio_data[6] = 0xff;
+    radio_data.to_vec()
+}
+
 fn test_msg_reversal_w_corruption(msg: &Message<22>) {
+    test_msg_reversal_w_channel(msg, burst);
+}
+
+fn test_msg_reversal_w_channel(
+    msg: &Message<22>,
+    mut channel: impl FnMut(&PacketWithoutDC) -> Vec<u8>,
+) {
     let mut wire_packets = Vec::new();
     let mut radio_packets = Vec::new();
 
@@ -16,16 +36,7 @@ fn test_msg_reversal_w_corruption(msg: &Message<22>) {
     while sender.data_to_send() {
         let wire_packet = sender.packet();
         wire_packets.push(wire_packet.clone());
-        let mut radio_data = wire_packet.encode_for_transmit().data();
-
-        // Simulate a burst error affecting 1/8 of the message
-        // TODO more sofisticated test
-        radio_data[3] = 0xff;
-        radio_data[4] = 0xff;
-        radio_data[5] = 0xff;
-        radio_data[6] = 0xff;
-
-        radio_packets.push(radio_data);
+        radio_packets.push(channel(&wire_packet.encode_for_transmit()));
     }
 
     // Reception and decode
@@ -108,3 +119,56 @@ pub fn test_long_v2_reversal_w_noise() {
     }
     test_msg_reversal_w_corruption(&msg);
 }
+
+#[cfg(feature = "sim")]
+fn long_v2_message() -> Message<22> {
+    let mut msg: Message<22> = Message::default();
+    msg.source_address = 0x55;
+    msg.packet_type = Some(LasoPacketType::GsmStatus.into());
+    msg.version = MessageVersion::V2;
+    for b in 0..20 {
+        msg.add(b as u8);
+    }
+    msg
+}
+
+#[test]
+#[cfg(feature = "sim")]
+pub fn test_random_bit_errors() {
+    use laso_packet::sim::{Channel, ChannelConfig};
+
+    for seed in 0..200 {
+        let mut channel = Channel::new(ChannelConfig::ber(1e-3), seed);
+        test_msg_reversal_w_channel(&long_v2_message(), |p| {
+            channel.transmit(p).unwrap().to_vec()
+        });
+    }
+}
+
+#[test]
+#[cfg(feature = "sim")]
+pub fn test_random_bursts() {
+    use laso_packet::sim::{Channel, ChannelConfig, GilbertElliott};
+
+    // Half of the packets see a burst of 8 bits on average, the
+    // interleaver spreads a burst over all codewords. Long bursts
+    // and two bursts close to each other can't be corrected.
+    let config = ChannelConfig {
+        bursts: Some(GilbertElliott::bursts(2e-3, 8.0)),
+        ..Default::default()
+    };
+    let received = (0..200)
+        .filter(|seed| {
+            let mut channel = Channel::new(config, *seed);
+            let mut rx: RxMessageDecoder<22> = RxMessageDecoder::default();
+            for p in MessageSender::new(long_v2_message()) {
+                let data = channel.transmit(&p).unwrap();
+                if rx.append(&block_on(decode_with_breaks(&data))).is_err() {
+                    return false;
+                }
+            }
+            rx.msg == long_v2_message()
+        })
+        .count();
+    assert!(received >= 190, "Only {received} of 200 messages received");
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::field_reassign_with_default)]
+
 pub(crate) use futures_lite::future::block_on;
 use laso_packet::{
     behavior::decode_with_breaks,
@@ -0,0 +1,215 @@
+#![allow(clippy::field_reassign_with_default)]
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+use laso_packet::{
+    frame::Framing,
+    lbt::{LbtConfig, LbtError, LbtScheduler, Radio},
+    message::{Message, MessageVersion},
+    tx::MessageSender,
+};
+
+// Microseconds per byte at 4800 bps
+const BYTE_US: u64 = 8 * 1_000_000 / 4800;
+
+// Channel shared by all simulated radios. Every node runs in its own
+// thread with its own virtual clock, a node may only touch the channel
+// when no other node is behind it in time.
+#[derive(Default)]
+struct Channel {
+    // Clock of each node, None when the node is done
+    clocks: Vec<Option<u64>>,
+    // Time intervals during which somebody was transmitting
+    busy: Vec<(u64, u64)>,
+}
+
+impl Channel {
+    fn busy_at(&self, t: u64) -> bool {
+        self.busy.iter().any(|(start, end)| *start <= t && t < *end)
+    }
+
+    fn overlaps(&self) -> bool {
+        self.busy
+            .iter()
+            .enumerate()
+            .any(|(i, a)| self.busy[i + 1..].iter().any(|b| a.0 < b.1 && b.0 < a.1))
+    }
+}
+
+#[derive(Clone, Default)]
+struct SharedChannel(Arc<(Mutex<Channel>, Condvar)>);
+
+impl SharedChannel {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Channel> {
+        self.0 .0.lock().unwrap()
+    }
+
+    fn join(&self, now: u64) -> usize {
+        let mut ch = self.lock();
+        ch.clocks.push(Some(now));
+        ch.clocks.len() - 1
+    }
+
+    fn set_clock(&self, node: usize, clock: Option<u64>) {
+        self.lock().clocks[node] = clock;
+        self.0 .1.notify_all();
+    }
+
+    // Wait until all other nodes caught up with our clock
+    fn with<T>(&self, node: usize, f: impl FnOnce(&mut Channel, u64) -> T) -> T {
+        let mut ch = self
+            .0
+             .1
+            .wait_while(self.lock(), |ch| {
+                let now = ch.clocks[node].unwrap();
+                ch.clocks.iter().flatten().any(|t| *t < now)
+            })
+            .unwrap();
+        let now = ch.clocks[node].unwrap();
+        f(&mut ch, now)
+    }
+}
+
+struct SimRadio {
+    channel: SharedChannel,
+    node: usize,
+    now: u64,
+}
+
+impl SimRadio {
+    fn new(channel: &SharedChannel, now: u64) -> Self {
+        Self {
+            channel: channel.clone(),
+            node: channel.join(now),
+            now,
+        }
+    }
+
+    fn advance(&mut self, us: u64) {
+        self.now += us;
+        self.channel.set_clock(self.node, Some(self.now));
+    }
+}
+
+impl Drop for SimRadio {
+    fn drop(&mut self) {
+        self.channel.set_clock(self.node, None);
+    }
+}
+
+impl Radio for SimRadio {
+    type Error = ();
+
+    fn rssi(&mut self) -> Result<i16, ()> {
+        let busy = self.channel.with(self.node, |ch, now| ch.busy_at(now));
+        Ok(if busy { -60 } else { -110 })
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), ()> {
+        let airtime = frame.len() as u64 * BYTE_US;
+        self.channel
+            .with(self.node, |ch, now| ch.busy.push((now, now + airtime)));
+        self.advance(airtime);
+        Ok(())
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.advance(us as u64);
+    }
+}
+
+fn test_message(source_address: u32) -> Message<64> {
+    let mut msg = Message::default();
+    msg.version = MessageVersion::V2;
+    msg.source_address = source_address;
+    msg.data.extend_from_slice(&[0x55; 20]).unwrap();
+    msg
+}
+
+#[test]
+fn test_clear_channel() {
+    let channel = SharedChannel::default();
+    let mut lbt = LbtScheduler::new(SimRadio::new(&channel, 0), LbtConfig::default(), 1);
+
+    let stats = lbt
+        .send(MessageSender::new(test_message(1)), Framing::NONE)
+        .unwrap();
+    assert_eq!(stats.busy, 0);
+    assert_eq!(stats.packets, 2);
+    drop(lbt);
+
+    // Listen time of 5 samples, then both packets back to back
+    let ch = channel.lock();
+    assert_eq!(ch.busy[0].0, 4000);
+    assert_eq!(ch.busy[0].1, ch.busy[1].0);
+}
+
+#[test]
+fn test_shared_channel() {
+    let channel = SharedChannel::default();
+
+    // Backoff slot of about one packet at this bitrate
+    let mut config = LbtConfig::default();
+    config.slot_us = 50_000;
+    config.max_attempts = 16;
+
+    // All nodes want to send at about the same time. The radios join
+    // the channel before any thread starts, so nobody runs ahead.
+    let nodes: Vec<_> = [(1, 0), (2, 100), (3, 2_000), (4, 2_500)]
+        .into_iter()
+        .map(|(node, start)| (node, SimRadio::new(&channel, start)))
+        .collect();
+    let handles: Vec<_> = nodes
+        .into_iter()
+        .map(|(node, radio)| {
+            thread::spawn(move || {
+                let mut lbt = LbtScheduler::new(radio, config, node);
+                lbt.send(MessageSender::new(test_message(node)), Framing::NONE)
+                    .unwrap()
+            })
+        })
+        .collect();
+    let busy: usize = handles
+        .into_iter()
+        .map(|h| h.join().unwrap().busy as usize)
+        .sum();
+
+    // Node 1 finds a free channel, the others have to back off
+    assert!(busy >= 3);
+    let ch = channel.lock();
+    assert_eq!(ch.busy.len(), 8);
+    assert!(!ch.overlaps());
+}
+
+#[test]
+fn test_busy_channel() {
+    let channel = SharedChannel::default();
+    channel.lock().busy.push((0, u64::MAX));
+
+    let mut config = LbtConfig::default();
+    config.max_attempts = 3;
+    let mut lbt = LbtScheduler::new(SimRadio::new(&channel, 0), config, 1);
+    assert_eq!(
+        lbt.send(MessageSender::new(test_message(1)), Framing::NONE),
+        Err(LbtError::ChannelBusy)
+    );
+    drop(lbt);
+    assert_eq!(channel.lock().busy.len(), 1);
+}
+
+#[test]
+fn test_widest_backoff() {
+    let channel = SharedChannel::default();
+    channel.lock().busy.push((0, u64::MAX));
+
+    let mut config = LbtConfig::default();
+    config.slot_us = 1;
+    config.min_backoff_exp = u8::MAX;
+    config.max_backoff_exp = u8::MAX;
+    config.max_attempts = 3;
+    let mut lbt = LbtScheduler::new(SimRadio::new(&channel, 0), config, 1);
+    assert_eq!(lbt.wait_clear(), Err(LbtError::ChannelBusy));
+}
@@ -0,0 +1,349 @@
+#![allow(clippy::field_reassign_with_default)]
+
+use std::collections::VecDeque;
+
+use futures_lite::future::block_on;
+use laso_packet::{
+    duty::{DutyCycleError, DutyCycleLimiter, EU_BANDS},
+    frame::Framing,
+    interleave::PacketInterleave,
+    line::LineCoding,
+    link::{
+        receive_message, receive_message_framed, send_message, send_message_attempt, send_request,
+        send_request_attempt, LinkError, LinkTiming, RadioRx, RadioTx, RxPacket,
+    },
+    message::{Message, MessageVersion},
+    plan::TxPlan,
+    rx::{RxErrorKind, RxMessage},
+    tx::MessageSender,
+};
+
+const FREQ: u32 = 868_300_000;
+
+// Everything transmitted is received back in the same order
+#[derive(Clone, Default)]
+struct Loopback {
+    air: VecDeque<Vec<u8>>,
+    delays: Vec<u32>,
+    timeouts: Vec<u32>,
+}
+
+impl RadioTx for Loopback {
+    type Error = ();
+
+    async fn transmit(&mut self, frame: &[u8]) -> Result<(), ()> {
+        self.air.push_back(frame.to_vec());
+        Ok(())
+    }
+
+    async fn delay_us(&mut self, us: u32) {
+        self.delays.push(us);
+    }
+}
+
+impl RadioRx for Loopback {
+    type Error = ();
+
+    async fn receive(&mut self, packet: &mut RxPacket, timeout_us: u32) -> Result<bool, ()> {
+        self.timeouts.push(timeout_us);
+        match self.air.pop_front() {
+            Some(frame) => {
+                packet.packet.extend_from_slice(&frame).unwrap();
+                packet.rssi = 0x40;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn test_message(version: MessageVersion, len: usize, will_listen: bool) -> Message<64> {
+    let mut msg = Message::default();
+    msg.version = version;
+    msg.source_address = 0x42;
+    msg.packet_type = Some(0x10);
+    msg.will_listen = will_listen;
+    msg.data.extend((0..len).map(|b| b as u8));
+    msg
+}
+
+fn receive(
+    radio: &mut Loopback,
+    timing: &LinkTiming,
+) -> Result<Option<RxMessage<64>>, LinkError<()>> {
+    block_on(receive_message(radio, timing, 1_000_000))
+}
+
+#[test]
+fn test_roundtrip() {
+    let timing = LinkTiming::default();
+    let mut radio = Loopback::default();
+
+    // Three packets, the last one fully used
+    let msg = test_message(MessageVersion::V2, 31, true);
+    let sent = block_on(send_message(
+        &mut radio,
+        MessageSender::new(msg.clone()),
+        Framing::NONE,
+        &timing,
+    ))
+    .unwrap();
+    assert_eq!(sent, 3);
+    assert_eq!(radio.delays, [timing.inter_packet_us; 2]);
+
+    let rx = receive(&mut radio, &timing).unwrap().unwrap();
+    assert_eq!(rx.msg, msg);
+    assert_eq!(rx.rssi, 0x40);
+    // The end of a long V2 message is detected by the timeout
+    assert_eq!(
+        radio.timeouts,
+        [
+            1_000_000,
+            timing.packet_timeout_us,
+            timing.packet_timeout_us,
+            timing.packet_timeout_us
+        ]
+    );
+}
+
+#[test]
+fn test_line_codes() {
+    let timing = LinkTiming::default();
+    for code in [
+        LineCoding::Manchester,
+        LineCoding::Pn9,
+        LineCoding::Identity,
+    ] {
+        let mut radio = Loopback::default();
+        let msg = test_message(MessageVersion::V2, 20, false);
+        let framing = Framing::NONE.with_line_code(code);
+        block_on(send_message(
+            &mut radio,
+            MessageSender::new(msg.clone()),
+            framing,
+            &timing,
+        ))
+        .unwrap();
+        assert!(radio.air.iter().all(|f| f.len() == framing.frame_size()));
+
+        let rx: RxMessage<64> = block_on(receive_message_framed(
+            &mut radio, &framing, &timing, 1_000_000,
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(rx.msg, msg, "{code:?}");
+    }
+}
+
+#[test]
+fn test_cross_interleave() {
+    let timing = LinkTiming::default();
+    for depth in [1, 2, 4, 8] {
+        let mut radio = Loopback::default();
+        // Nine fully used packets, the last block is shorter
+        let mut msg: Message<128> = Message::default();
+        msg.version = MessageVersion::V2;
+        msg.source_address = 0x42;
+        msg.packet_type = Some(0x10);
+        msg.data.extend((0..97).map(|b| b as u8));
+        let framing = Framing::NONE.with_interleave_depth(depth);
+        let frames = MessageSender::new(msg.clone()).frames(framing);
+        assert_eq!(frames.len(), 9);
+        let sent = block_on(send_message(
+            &mut radio,
+            MessageSender::new(msg.clone()),
+            framing,
+            &timing,
+        ))
+        .unwrap();
+        assert_eq!(sent, 9);
+
+        let mut fade = radio.clone();
+        let mut last_fade = radio.clone();
+        let rx: RxMessage<128> = block_on(receive_message_framed(
+            &mut radio, &framing, &timing, 1_000_000,
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(rx.msg, msg, "depth {depth}");
+
+        // A fade wipes the second frame, a frame costs each codeword
+        // 24 / depth bits and only depth 8 stays within what Golay corrects
+        fade.air[1].fill(0x00);
+        let rx: Result<Option<RxMessage<128>>, _> = block_on(receive_message_framed(
+            &mut fade, &framing, &timing, 1_000_000,
+        ));
+        match depth {
+            8 => assert_eq!(rx.unwrap().unwrap().msg, msg),
+            _ => assert!(rx.is_err()),
+        }
+
+        // The last block holds a single packet at depth 8, a fade on the
+        // last frame loses the message at every depth
+        last_fade.air[8].fill(0x00);
+        let rx: Result<Option<RxMessage<128>>, _> = block_on(receive_message_framed(
+            &mut last_fade,
+            &framing,
+            &timing,
+            1_000_000,
+        ));
+        assert!(rx.is_err(), "depth {depth}");
+    }
+}
+
+#[test]
+fn test_packet_interleave() {
+    let timing = LinkTiming::default();
+    for depth in [1, 4] {
+        let mut radio = Loopback::default();
+        let msg = test_message(MessageVersion::V2, 31, false);
+        let framing = Framing::NONE
+            .with_packet_interleave(PacketInterleave::Sequential)
+            .with_interleave_depth(depth);
+        let sequential: Vec<_> = MessageSender::new(msg.clone()).frames(framing).collect();
+        let spread: Vec<_> = MessageSender::new(msg.clone())
+            .frames(framing.with_packet_interleave(PacketInterleave::Spread))
+            .collect();
+        assert_ne!(sequential, spread);
+
+        block_on(send_message(
+            &mut radio,
+            MessageSender::new(msg.clone()),
+            framing,
+            &timing,
+        ))
+        .unwrap();
+        let rx: RxMessage<64> = block_on(receive_message_framed(
+            &mut radio, &framing, &timing, 1_000_000,
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(rx.msg, msg, "depth {depth}");
+    }
+}
+
+#[test]
+fn test_nothing_received() {
+    let mut radio = Loopback::default();
+    assert!(receive(&mut radio, &LinkTiming::default())
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_truncated() {
+    let timing = LinkTiming::default();
+    let mut radio = Loopback::default();
+    let msg = test_message(MessageVersion::V2, 5, false);
+    block_on(send_message(
+        &mut radio,
+        MessageSender::new(msg),
+        Framing::NONE,
+        &timing,
+    ))
+    .unwrap();
+
+    // Lose the CRC packet
+    radio.air.pop_back();
+    match receive(&mut radio, &timing) {
+        Err(LinkError::Decode(err)) => {
+            assert_eq!(err.kind, RxErrorKind::Truncated);
+            assert_eq!(err.packet, 1);
+        }
+        _ => panic!("Expected a truncated message"),
+    }
+}
+
+#[test]
+fn test_listen_window() {
+    let timing = LinkTiming::default();
+    let mut radio = Loopback::default();
+
+    // Short message is complete after a single packet, no timeout needed
+    let reply = test_message(MessageVersion::V2Short, 8, false);
+    let request = test_message(MessageVersion::V2Short, 4, true);
+    block_on(send_message(
+        &mut radio,
+        MessageSender::new(request.clone()),
+        Framing::NONE,
+        &timing,
+    ))
+    .unwrap();
+    let rx = receive(&mut radio, &timing).unwrap().unwrap();
+    assert!(rx.msg.will_listen);
+    radio.timeouts.clear();
+
+    // The reply is queued before the request, the loopback
+    // returns it first
+    block_on(send_message(
+        &mut radio,
+        MessageSender::new(reply.clone()),
+        Framing::NONE,
+        &timing,
+    ))
+    .unwrap();
+    let rx: Option<RxMessage<64>> = block_on(send_request(
+        &mut radio,
+        MessageSender::new(request),
+        Framing::NONE,
+        &timing,
+    ))
+    .unwrap();
+    assert_eq!(rx.unwrap().msg, reply);
+    assert_eq!(radio.timeouts, [timing.listen_window_us]);
+
+    // No listening without the flag
+    radio.timeouts.clear();
+    let rx: Option<RxMessage<64>> = block_on(send_request(
+        &mut radio,
+        MessageSender::new(reply),
+        Framing::NONE,
+        &timing,
+    ))
+    .unwrap();
+    assert!(rx.is_none());
+    assert!(radio.timeouts.is_empty());
+}
+
+#[test]
+fn test_retry_budget() {
+    let timing = LinkTiming::default();
+    let mut radio = Loopback::default();
+    let msg = test_message(MessageVersion::V2Short, 4, false);
+    let framing = Framing::NONE;
+    let plan = TxPlan::for_message(&msg, &framing, 38_400);
+
+    // Two attempts
+    let mut limiter = DutyCycleLimiter::new(EU_BANDS);
+    let mut retries = limiter
+        .retries(FREQ, plan.airtime_us, 2, 0)
+        .expect("Budget too small");
+    for now_ms in [0, 1_000] {
+        let rx: Option<RxMessage<64>> = block_on(send_request_attempt(
+            &mut radio,
+            MessageSender::new(msg.clone()),
+            framing,
+            &timing,
+            &mut limiter,
+            &mut retries,
+            now_ms,
+        ))
+        .unwrap();
+        assert!(rx.is_none());
+    }
+    assert_eq!(radio.air.len(), 2);
+    assert_eq!(limiter.used_us(FREQ, 1_000), Ok(2 * plan.airtime_us));
+
+    // Nothing goes on air once the attempts are used up
+    let sent = block_on(send_message_attempt(
+        &mut radio,
+        MessageSender::new(msg),
+        framing,
+        &timing,
+        &mut limiter,
+        &mut retries,
+        2_000,
+    ));
+    assert_eq!(sent, Err(LinkError::DutyCycle(DutyCycleError::Exhausted)));
+    assert_eq!(radio.air.len(), 2);
+}
@@ -0,0 +1,137 @@
+#![cfg(feature = "std")]
+#![allow(clippy::field_reassign_with_default)]
+
+use std::{env, fs, process};
+
+use laso_packet::{
+    frame::{FrameSync, Framing},
+    line::{LineCode as _, LineCoding},
+    message::{Message, MessageVersion},
+    modem::{read_iq, write_iq, Demodulator, Iq, ModemConfig, Modulation, Modulator, SampleFormat},
+    rx::RxMessageDecoder,
+    tx::MessageSender,
+};
+
+const SYNC: [u8; 2] = [0x2d, 0xd4];
+
+fn test_message(n: u32) -> Message<64> {
+    let mut msg = Message::default();
+    msg.version = MessageVersion::V2;
+    msg.source_address = 0x1234 + n;
+    msg.packet_type = Some(0x10);
+    msg.data.extend((0..31).map(|b| (b as u32 * 7 + n) as u8));
+    msg
+}
+
+// Gaussian noise, xorshift and Box-Muller
+struct Noise(u64);
+
+impl Noise {
+    fn uniform(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        ((self.0 >> 11) as f64 / (1u64 << 53) as f64) as f32
+    }
+
+    fn add(&mut self, samples: &mut [Iq], sigma: f32) {
+        for s in samples {
+            let r = (-2.0 * (1.0 - self.uniform()).ln()).sqrt() * sigma;
+            let a = std::f32::consts::TAU * self.uniform();
+            s.i += r * a.cos();
+            s.q += r * a.sin();
+        }
+    }
+}
+
+fn round_trip(config: ModemConfig, format: SampleFormat, sigma: f32) {
+    round_trip_framed(config, format, sigma, Framing::new(4, &SYNC));
+}
+
+fn round_trip_framed(config: ModemConfig, format: SampleFormat, sigma: f32, framing: Framing) {
+    let messages: Vec<Message<64>> = (0..3).map(test_message).collect();
+
+    let mut samples = Vec::new();
+    let mut m = Modulator::new(config);
+    let mut sent = Vec::new();
+    for msg in &messages {
+        m.silence(500, &mut samples);
+        let frames: Vec<_> = MessageSender::new(msg.clone()).frames(framing).collect();
+        for frame in &frames {
+            m.modulate(frame, &mut samples);
+            m.silence(100, &mut samples);
+        }
+        sent.push(frames.len());
+    }
+    m.silence(500, &mut samples);
+    Noise(0x1234_5678).add(&mut samples, sigma);
+
+    let path = env::temp_dir().join(format!(
+        "laso_modem_{}_{:?}_{:?}.iq",
+        process::id(),
+        format,
+        config.modulation
+    ));
+    write_iq(fs::File::create(&path).unwrap(), format, &samples).unwrap();
+    let read = read_iq(fs::File::open(&path).unwrap(), format).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(read.len(), samples.len());
+
+    let mut sync = FrameSync::for_framing(&framing, 1);
+    let packets = Demodulator::new(config).receive(&read, &mut sync);
+    assert_eq!(
+        packets.len(),
+        sent.iter().sum::<usize>(),
+        "{config:?} {format:?}"
+    );
+
+    let mut packets = packets.iter();
+    for (msg, sent) in messages.iter().zip(sent) {
+        let mut rx: RxMessageDecoder<64> = RxMessageDecoder::default();
+        for p in packets.by_ref().take(sent) {
+            rx.append(&framing.line_code.decode_packet(p)).unwrap();
+        }
+        let rx = rx.take_message().unwrap();
+        assert_eq!(rx.msg.source_address, msg.source_address);
+        assert!(rx.msg.data.starts_with(&msg.data));
+    }
+}
+
+#[test]
+fn test_fsk() {
+    let config = ModemConfig::default();
+    round_trip(config, SampleFormat::Cu8, 0.15);
+    round_trip(config, SampleFormat::Cs16, 0.15);
+
+    // Sample rate not a multiple of the bitrate
+    let config = ModemConfig {
+        sample_rate: 250_000,
+        bitrate: 38_400,
+        modulation: Modulation::Fsk {
+            deviation_hz: 20_000.0,
+        },
+    };
+    round_trip(config, SampleFormat::Cs16, 0.05);
+}
+
+#[test]
+fn test_ook() {
+    let config = ModemConfig {
+        sample_rate: 250_000,
+        bitrate: 10_000,
+        modulation: Modulation::Ook,
+    };
+    round_trip(config, SampleFormat::Cu8, 0.15);
+    round_trip(config, SampleFormat::Cs16, 0.15);
+}
+
+#[test]
+fn test_ook_manchester() {
+    let config = ModemConfig {
+        sample_rate: 250_000,
+        bitrate: 10_000,
+        modulation: Modulation::Ook,
+    };
+    let framing = Framing::new(4, &SYNC).with_line_code(LineCoding::Manchester);
+    round_trip_framed(config, SampleFormat::Cs16, 0.15, framing);
+}